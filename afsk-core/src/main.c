@@ -23,6 +23,39 @@ struct demod_config {
     uint32_t baud_rate;
 };
 
+// A point at which the transmitter changed tones.  The receiver retunes its
+// filters when it reaches `start_sample`.
+struct demod_hop {
+    uint32_t start_sample;
+    uint32_t f_lo;
+    uint32_t f_hi;
+};
+
+// Something the MAC did, and the sample it did it at
+struct demod_event {
+    uint32_t sample;
+    uint32_t kind;
+};
+
+#define DEMOD_EVENT_SYNC 1
+#define DEMOD_EVENT_PACKET_OK 2
+#define DEMOD_EVENT_PACKET_CORRUPT 3
+
+// A record of what the receiver saw, so the harness can line it up with what
+// was sent.  Each bit is stored as a byte, alongside the sample at which the
+// demodulator decided on it.  Anything past `max_bits` or `max_events` is
+// dropped, but still counted.
+struct demod_trace {
+    uint8_t *bits;
+    uint32_t *bit_samples;
+    uint32_t max_bits;
+    uint32_t nbits;
+
+    struct demod_event *events;
+    uint32_t max_events;
+    uint32_t nevents;
+};
+
 uint32_t debug_print_sync = 0;
 
 // static int dst_fd;
@@ -142,41 +175,93 @@ static int validate_packet(demod_pkt_t *pkt, int should_print) {
 }
 
 #ifdef NO_MAIN
-uint32_t attempt_demodulation(struct demod_config *cfg, int16_t *samples,
-                         uint32_t nsamples) {
+static void trace_event(struct demod_trace *trace, uint32_t sample,
+                        uint32_t kind) {
+    if (!trace) return;
+    if (trace->nevents < trace->max_events) {
+        trace->events[trace->nevents].sample = sample;
+        trace->events[trace->nevents].kind = kind;
+    }
+    trace->nevents++;
+}
+
+static uint32_t demodulate(struct demod_config *cfg, struct demod_hop *hops,
+                           uint32_t nhops, int16_t *samples,
+                           uint32_t nsamples, struct demod_trace *trace) {
     FSK_demod_const demod_table;
     FSK_demod_state demod_state;
     struct mac_state mac_state;
     int packet_count = 0;
     int corrupt_count = 0;
     demod_pkt_t packet;
+    uint32_t next_hop = 0;
+    uint32_t position = 0;
 
     memset(&mac_state, 0, sizeof(mac_state));
     fsk_demod_generate_table(&demod_table, cfg->baud_rate, cfg->sample_rate,
                              cfg->f_lo, cfg->f_hi, cfg->filter_width);
     fsk_demod_init(&demod_table, &demod_state);
 
-    uint32_t remaining_words = nsamples;
-    int16_t *decoding_buffer_offset = samples;
+    // The correlators look back over `filter_width` samples, so retune once
+    // the middle of that window reaches the hop rather than its leading edge.
+    uint32_t hop_delay = cfg->filter_width / 2;
+
+    while (position < nsamples) {
+        // Follow the transmitter to its next pair of tones.  Only the
+        // filter tables change, so the PLL and filter history carry on.
+        while (next_hop < nhops &&
+               hops[next_hop].start_sample + hop_delay <= position) {
+            fsk_demod_generate_table(&demod_table, cfg->baud_rate,
+                                     cfg->sample_rate, hops[next_hop].f_lo,
+                                     hops[next_hop].f_hi, cfg->filter_width);
+            next_hop++;
+        }
+
+        uint32_t chunk_end = nsamples;
+        if (next_hop < nhops &&
+            hops[next_hop].start_sample + hop_delay < chunk_end)
+            chunk_end = hops[next_hop].start_sample + hop_delay;
 
-    while (remaining_words > 0) {
         int bit = 0;
         int result = fsk_demod(&demod_table, &demod_state, &bit,
-                               decoding_buffer_offset, remaining_words);
-        if (result != -1) {
-            if (mac_put_bit(&mac_state, bit, &packet, sizeof(packet))) {
-                if (validate_packet(&packet, 0)) {
-                    packet_count++;
-                } else {
-                    corrupt_count++;
-                }
+                               samples + position, chunk_end - position);
+        if (result == -1) {
+            position = chunk_end;
+            continue;
+        }
+        position = chunk_end - result;
+        if (trace) {
+            if (trace->nbits < trace->max_bits) {
+                trace->bits[trace->nbits] = bit;
+                trace->bit_samples[trace->nbits] = position;
+            }
+            trace->nbits++;
+        }
+        current_mac_state_t last_mstate = mac_state.mstate;
+        if (mac_put_bit(&mac_state, bit, &packet, sizeof(packet))) {
+            if (validate_packet(&packet, 0)) {
+                packet_count++;
+                trace_event(trace, position, DEMOD_EVENT_PACKET_OK);
+            } else {
+                corrupt_count++;
+                trace_event(trace, position, DEMOD_EVENT_PACKET_CORRUPT);
             }
-            decoding_buffer_offset += (remaining_words - result);
-            remaining_words = result;
+        } else if (last_mstate != MAC_PACKET && mac_state.mstate == MAC_PACKET) {
+            trace_event(trace, position, DEMOD_EVENT_SYNC);
         }
     }
     return packet_count;
 }
+
+// Demodulate `samples`, retuning at each of the `hops` and recording every
+// bit and MAC event in `trace`, and return the number of good packets.
+// `hops` may be NULL if `nhops` is zero, and `trace` may be NULL.
+uint32_t attempt_demodulation_traced(struct demod_config *cfg,
+                                     struct demod_hop *hops, uint32_t nhops,
+                                     int16_t *samples, uint32_t nsamples,
+                                     struct demod_trace *trace) {
+    return demodulate(cfg, hops, nhops, samples, nsamples, trace);
+}
 #else
 int main(int argc, char **argv) {
     FSK_demod_const demod_table;
@@ -1,4 +1,3 @@
-use cc;
 use std::path::PathBuf;
 
 fn main() {
@@ -0,0 +1,393 @@
+use crate::controller::SentPacket;
+
+/// How far either side of where a packet ought to be to look for it, in
+/// bits.  This covers the delay through the receive filter and the channel,
+/// as well as a fair amount of clock drift.
+const SEARCH_BITS: usize = 64;
+
+/// How many bits at the start of a packet are used to line it up
+const ALIGN_BITS: usize = 64;
+
+/// The stretch of bits checked at a time for the receiver having slipped
+const SLIP_BLOCK_BITS: usize = 32;
+
+/// The furthest the receiver is expected to slip within one block
+const MAX_SLIP: isize = 2;
+
+/// A block is only taken to have slipped if, lined up again, no more than
+/// one in this many of its bits are wrong.  Otherwise a block of noise would
+/// often line up better somewhere by chance.
+const SLIP_MATCH: usize = 8;
+
+/// Something the receiver's MAC did, as reported by the demodulator
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Event {
+    pub sample: u32,
+    pub kind: u32,
+}
+
+/// The MAC found a sync word
+pub const EVENT_SYNC: u32 = 1;
+
+/// The MAC received a whole packet, and its hash matched
+pub const EVENT_PACKET_OK: u32 = 2;
+
+/// Everything the receiver decided on, in order
+#[derive(Clone, Debug, Default)]
+pub struct Reception {
+    pub bits: Vec<u8>,
+
+    /// The sample at which each bit was decided on
+    pub bit_samples: Vec<u32>,
+
+    pub events: Vec<Event>,
+}
+
+/// How one packet fared at the bit level
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PacketErrors {
+    /// The number of bits compared, which is every bit from the sync word on
+    pub bits: usize,
+    pub errors: usize,
+
+    /// How many times the receiver gained or lost bits partway through
+    pub slips: usize,
+
+    /// Whether the receiver's MAC found the packet's sync word
+    pub synced: bool,
+
+    /// Whether the receiver got the whole packet with its hash intact
+    pub passed: bool,
+}
+
+/// Bit errors summed over any number of packets
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BitErrors {
+    pub bits: usize,
+    pub errors: usize,
+    pub slips: usize,
+
+    /// Packets whose sync word the receiver never found
+    pub sync_losses: usize,
+}
+
+impl BitErrors {
+    pub fn add(&mut self, other: &BitErrors) {
+        self.bits += other.bits;
+        self.errors += other.errors;
+        self.slips += other.slips;
+        self.sync_losses += other.sync_losses;
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.errors as f64 / self.bits as f64
+    }
+}
+
+impl<'a> std::iter::FromIterator<&'a PacketErrors> for BitErrors {
+    fn from_iter<I: IntoIterator<Item = &'a PacketErrors>>(packets: I) -> Self {
+        let mut total = BitErrors::default();
+        for packet in packets {
+            total.add(&BitErrors {
+                bits: packet.bits,
+                errors: packet.errors,
+                slips: packet.slips,
+                sync_losses: !packet.synced as usize,
+            });
+        }
+        total
+    }
+}
+
+/// Count the errors between the bits sent and those received from
+/// `start`, where a bit that was never received counts as an error
+fn count_errors(sent: &[u8], received: &[u8], start: isize) -> usize {
+    sent.iter()
+        .enumerate()
+        .filter(|(i, bit)| {
+            let index = start + *i as isize;
+            index < 0 || received.get(index as usize) != Some(bit)
+        })
+        .count()
+}
+
+/// Line each packet that was sent up with the bits the receiver decided on
+/// around the same time, and count the differences.  The run of zeroes at
+/// the start of the preamble is left out, as it could be lined up anywhere
+/// within a run of zeroes the receiver heard.
+///
+/// Where the receiver slipped by a bit or two partway through a packet, the
+/// bits after the slip are lined up again rather than all being counted as
+/// errors, and the slip is counted instead.  The receiver's clock may run
+/// at a different rate to the transmitter's, so each packet is looked for
+/// relative to where the one before it was found.
+pub fn measure(
+    packets: &[SentPacket],
+    reception: &Reception,
+    samples_per_bit: f64,
+) -> Vec<PacketErrors> {
+    let mut timing = 0.0;
+    packets
+        .iter()
+        .map(|packet| {
+            let (errors, found) = measure_packet(packet, reception, samples_per_bit, timing);
+            if let Some(found) = found {
+                timing = found;
+            }
+            errors
+        })
+        .collect()
+}
+
+/// Measure one packet, expecting it `timing` samples after it was sent.
+/// Also returns how late the packet actually was, if it could be found.
+fn measure_packet(
+    packet: &SentPacket,
+    reception: &Reception,
+    samples_per_bit: f64,
+    timing: f64,
+) -> (PacketErrors, Option<f64>) {
+    let leading_zeroes = packet.bytes.iter().take_while(|byte| **byte == 0).count();
+    let sent: Vec<u8> = packet.bytes[leading_zeroes..]
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1))
+        .collect();
+    let sent_start = packet.start_sample as f64 + (leading_zeroes * 8) as f64 * samples_per_bit;
+    let start = sent_start + timing;
+    let received = &reception.bits;
+
+    // Try every alignment near where the packet should start, favouring
+    // the nearest if several are as good
+    let nominal = reception
+        .bit_samples
+        .partition_point(|sample| (*sample as f64) < start) as isize;
+    let align = &sent[..sent.len().min(ALIGN_BITS)];
+    let mut offset = nominal;
+    let mut best = count_errors(align, received, nominal);
+    for distance in 1..=SEARCH_BITS as isize {
+        for candidate in [nominal - distance, nominal + distance] {
+            let errors = count_errors(align, received, candidate);
+            if errors < best {
+                best = errors;
+                offset = candidate;
+            }
+        }
+    }
+
+    // Only trust the timing of a packet that lined up well
+    let found = reception
+        .bit_samples
+        .get(offset.max(0) as usize)
+        .filter(|_| best <= align.len() / SLIP_MATCH)
+        .map(|sample| *sample as f64 - samples_per_bit - sent_start);
+
+    let mut errors = 0;
+    let mut slips = 0;
+    for (block, bits) in sent.chunks(SLIP_BLOCK_BITS).enumerate() {
+        let position = (block * SLIP_BLOCK_BITS) as isize;
+        let mut block_errors = count_errors(bits, received, offset + position);
+        if block_errors > bits.len() / 4 {
+            let slipped = (-MAX_SLIP..=MAX_SLIP)
+                .filter(|slip| *slip != 0)
+                .map(|slip| (slip, count_errors(bits, received, offset + slip + position)))
+                .min_by_key(|(_, errors)| *errors);
+            if let Some((slip, slipped_errors)) = slipped {
+                if slipped_errors <= bits.len() / SLIP_MATCH {
+                    offset += slip;
+                    slips += 1;
+                    block_errors = slipped_errors;
+                }
+            }
+        }
+        errors += block_errors;
+    }
+
+    // The MAC reports sync once it has seen the whole sync word, which
+    // follows straight after the zeroes
+    let window = SEARCH_BITS as f64 * samples_per_bit;
+    let sync_end = start + (3 * 8) as f64 * samples_per_bit;
+    let synced = reception.events.iter().any(|event| {
+        event.kind == EVENT_SYNC
+            && event.sample as f64 >= start - window
+            && event.sample as f64 <= sync_end + window
+    });
+    // ...and reports the packet once it has its hash, just before the end
+    let end = start + sent.len() as f64 * samples_per_bit;
+    let passed = reception.events.iter().any(|event| {
+        event.kind == EVENT_PACKET_OK
+            && event.sample as f64 >= sync_end
+            && event.sample as f64 <= end + window
+    });
+
+    let errors = PacketErrors {
+        bits: sent.len(),
+        errors,
+        slips,
+        synced,
+        passed,
+    };
+    (errors, found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::PacketKind;
+
+    const SAMPLES_PER_BIT: f64 = 6.0;
+
+    /// Bits between the start of one packet and the next
+    const PACKET_SPACING: usize = 2000;
+
+    /// Something like what the receiver decides on when there is no packet,
+    /// from a fixed linear congruential generator
+    fn filler(index: usize) -> u8 {
+        let state = (index as u64)
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 61) as u8 & 1
+    }
+
+    fn packets(count: usize) -> Vec<SentPacket> {
+        (0..count)
+            .map(|n| {
+                let mut bytes = vec![0, 0, 0, 0, 0x55, 0xd3, 0x91];
+                bytes.extend((0..24).map(|i| (n * 37 + i * 101) as u8));
+                SentPacket {
+                    kind: PacketKind::Data(n as u16),
+                    pass: 0,
+                    start_sample: (n * PACKET_SPACING + 100) * SAMPLES_PER_BIT as usize,
+                    bytes,
+                }
+            })
+            .collect()
+    }
+
+    fn packet_bits(packet: &SentPacket) -> Vec<u8> {
+        packet
+            .bytes
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1))
+            .collect()
+    }
+
+    /// What a receiver would decide on if it heard every packet perfectly,
+    /// with its clock running `ratio` times slower than the transmitter's.
+    /// Packets in `missing` are never heard.
+    fn receive(packets: &[SentPacket], ratio: f64, missing: &[usize]) -> Reception {
+        let total_bits = packets.len() * PACKET_SPACING + 200;
+        let mut bits: Vec<u8> = (0..total_bits).map(filler).collect();
+        let mut events = vec![];
+        for (n, packet) in packets.iter().enumerate() {
+            if missing.contains(&n) {
+                continue;
+            }
+            let first_bit = packet.start_sample / SAMPLES_PER_BIT as usize;
+            let sent = packet_bits(packet);
+            bits[first_bit..first_bit + sent.len()].copy_from_slice(&sent);
+            let sample_at = |bit: usize| ((bit + 1) as f64 * SAMPLES_PER_BIT * ratio) as u32;
+            events.push(Event {
+                sample: sample_at(first_bit + (4 + 3) * 8),
+                kind: EVENT_SYNC,
+            });
+            events.push(Event {
+                sample: sample_at(first_bit + sent.len() - 8),
+                kind: EVENT_PACKET_OK,
+            });
+        }
+        let bit_samples = (0..total_bits)
+            .map(|bit| ((bit + 1) as f64 * SAMPLES_PER_BIT * ratio) as u32)
+            .collect();
+        Reception {
+            bits,
+            bit_samples,
+            events,
+        }
+    }
+
+    /// The bits compared for each packet, which leave out the leading zeroes
+    const PACKET_BITS: usize = (7 + 24 - 4) * 8;
+
+    #[test]
+    fn perfect_reception() {
+        let packets = packets(4);
+        let errors = measure(&packets, &receive(&packets, 1.0, &[]), SAMPLES_PER_BIT);
+        for packet in &errors {
+            assert_eq!(packet.bits, PACKET_BITS);
+            assert_eq!(packet.errors, 0);
+            assert_eq!(packet.slips, 0);
+            assert!(packet.synced);
+            assert!(packet.passed);
+        }
+        let total: BitErrors = errors.iter().collect();
+        assert_eq!(total.bits, 4 * PACKET_BITS);
+        assert_eq!(total.rate(), 0.0);
+        assert_eq!(total.sync_losses, 0);
+    }
+
+    #[test]
+    fn flipped_bits() {
+        let packets = packets(3);
+        let mut reception = receive(&packets, 1.0, &[]);
+        let first_bit = packets[1].start_sample / SAMPLES_PER_BIT as usize;
+        for bit in [40, 100, 200] {
+            reception.bits[first_bit + bit] ^= 1;
+        }
+        let errors = measure(&packets, &reception, SAMPLES_PER_BIT);
+        assert_eq!(errors[0].errors, 0);
+        assert_eq!(errors[1].errors, 3);
+        assert_eq!(errors[1].slips, 0);
+        assert_eq!(errors[2].errors, 0);
+    }
+
+    #[test]
+    fn one_bit_slip() {
+        let packets = packets(3);
+        let mut reception = receive(&packets, 1.0, &[]);
+        // The receiver misses a bit right at the start of the third block
+        // that is compared, so everything after it arrives a bit early
+        let first_bit = packets[1].start_sample / SAMPLES_PER_BIT as usize;
+        let lost = first_bit + 4 * 8 + 2 * SLIP_BLOCK_BITS;
+        // Lined up again, the lost bit is compared with the one before it
+        let lost_error = (reception.bits[lost] != reception.bits[lost - 1]) as usize;
+        reception.bits.remove(lost);
+        reception.bit_samples.remove(lost);
+        let errors = measure(&packets, &reception, SAMPLES_PER_BIT);
+        assert_eq!(errors[1].slips, 1);
+        assert_eq!(errors[1].errors, lost_error);
+        assert_eq!(errors[2].slips, 0);
+        assert_eq!(errors[2].errors, 0);
+    }
+
+    #[test]
+    fn missing_packet() {
+        let packets = packets(4);
+        let errors = measure(&packets, &receive(&packets, 1.0, &[2]), SAMPLES_PER_BIT);
+        assert!(errors[2].errors > PACKET_BITS / 4);
+        assert!(!errors[2].synced);
+        assert!(!errors[2].passed);
+        for n in [0, 1, 3] {
+            assert_eq!(errors[n].errors, 0);
+            assert!(errors[n].synced);
+        }
+        let total: BitErrors = errors.iter().collect();
+        assert_eq!(total.sync_losses, 1);
+    }
+
+    #[test]
+    fn clock_offset_between_packets() {
+        // 1% slow: by the last packet the receiver is over a hundred bits
+        // behind, much further than a single search reaches
+        const _: () = assert!(PACKET_SPACING * 9 / 100 > SEARCH_BITS);
+        let packets = packets(10);
+        let reception = receive(&packets, 1.01, &[]);
+        let errors = measure(&packets, &reception, SAMPLES_PER_BIT);
+        for packet in &errors {
+            assert_eq!(packet.errors, 0);
+            assert_eq!(packet.slips, 0);
+            assert!(packet.synced);
+            assert!(packet.passed);
+        }
+    }
+}
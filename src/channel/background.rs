@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use super::recording::Recording;
+use super::{ChannelContext, ChannelStage};
+use crate::dsp;
+
+/// Where in the recording the noise starts
+#[derive(Clone, Copy, Debug)]
+pub enum Offset {
+    /// Somewhere different every run
+    Random,
+
+    /// This many seconds in
+    Seconds(f64),
+}
+
+/// A recording of the environment, such as air conditioning or a room full
+/// of people, mixed in at a given signal-to-noise ratio.  The recording is
+/// looped if it is shorter than the transmission.
+pub struct Background {
+    recording: Arc<Recording>,
+    offset: Offset,
+
+    /// Ratio of the signal power to the power of the recording within the
+    /// band used by the tones, in dB
+    snr_db: f64,
+
+    /// Gain applied to the recording in the last run, in dB
+    gain_db: f64,
+}
+
+impl Background {
+    pub fn new(
+        recording: Arc<Recording>,
+        snr_db: f64,
+        offset: Offset,
+    ) -> std::io::Result<Background> {
+        if recording.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: recording is empty", recording.path()),
+            ));
+        }
+        Ok(Background {
+            recording,
+            offset,
+            snr_db,
+            gain_db: f64::NEG_INFINITY,
+        })
+    }
+}
+
+impl ChannelStage for Background {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let recording = self.recording.resampled(ctx.sample_rate);
+        let start = match self.offset {
+            Offset::Random => ctx.rng.gen_range(0, recording.len()),
+            Offset::Seconds(seconds) => (seconds * ctx.sample_rate) as usize % recording.len(),
+        };
+        let noise: Vec<f64> = recording
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(samples.len())
+            .copied()
+            .collect();
+
+        // Only the noise that lands in the same band as the tones matters to
+        // the receiver, so measure the noise there.  The signal is entirely
+        // within that band already.
+        let nyquist = ctx.sample_rate / 2.0;
+        let low = (ctx.f_lo - ctx.baud_rate / 2.0).max(20.0);
+        let high = ctx.f_hi + ctx.baud_rate / 2.0;
+        let mut in_band = dsp::highpass_order(&noise, ctx.sample_rate, low, 4);
+        if high < nyquist * 0.95 {
+            in_band = dsp::lowpass_order(&in_band, ctx.sample_rate, high, 4);
+        }
+        let noise_power = dsp::energy(&in_band) / in_band.len().max(1) as f64;
+        let signal_power = dsp::active_power(samples, ctx.sample_rate);
+        if noise_power <= 0.0 || signal_power <= 0.0 {
+            self.gain_db = f64::NEG_INFINITY;
+            return;
+        }
+
+        let gain = (signal_power / (noise_power * dsp::db_to_power(self.snr_db))).sqrt();
+        self.gain_db = 20.0 * gain.log10();
+        for (sample, noise) in samples.iter_mut().zip(noise) {
+            *sample += noise * gain;
+        }
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["gain_db"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.gain_db]
+    }
+}
@@ -0,0 +1,72 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use super::{ChannelContext, ChannelStage};
+use crate::resample::Interpolator;
+
+/// The slowest the receiver's clock may run relative to the transmitter's,
+/// however far drift and wander take it.  This keeps each step through the
+/// transmitted signal positive and finite.
+const MIN_CLOCK_RATIO: f64 = 1e-3;
+
+/// How many received samples apart the stage records its timing
+const TIME_MAP_INTERVAL: usize = 64;
+
+/// A mismatch between the transmitter's and receiver's sample clocks.  The
+/// receiver's clock runs fast by `ppm` parts per million, changes by
+/// `drift` ppm every second, and wanders randomly around that by
+/// `wander` ppm.
+pub struct Clock {
+    ppm: f64,
+    drift: f64,
+    wander: f64,
+
+    /// How long the wander takes to change direction, in seconds
+    wander_secs: f64,
+}
+
+impl Clock {
+    pub fn new(ppm: f64, drift: f64, wander: f64, wander_secs: f64) -> Clock {
+        Clock {
+            ppm,
+            drift,
+            wander,
+            wander_secs,
+        }
+    }
+}
+
+impl ChannelStage for Clock {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        if self.ppm == 0.0 && self.drift == 0.0 && self.wander == 0.0 {
+            return;
+        }
+        let input = std::mem::take(samples);
+        let interpolator = Interpolator::new(1.0 / (1.0 + self.ppm.abs() * 1e-6));
+
+        // The wander is a random walk that is pulled back towards zero,
+        // which keeps it within about `wander` ppm of the nominal offset.
+        let pull = (1.0 / (self.wander_secs.max(1e-3) * ctx.sample_rate)).min(1.0);
+        let kick = self.wander * (2.0 * pull).sqrt();
+        let mut wander = 0.0;
+
+        // Step through the transmitted signal, advancing by one receiver
+        // sample period each time.
+        let mut position = 0.0;
+        let mut time_map = vec![];
+        while position < input.len() as f64 {
+            if samples.len().is_multiple_of(TIME_MAP_INTERVAL) {
+                time_map.push((position, samples.len() as f64));
+            }
+            samples.push(interpolator.sample(&input, position));
+            let seconds = samples.len() as f64 / ctx.sample_rate;
+            if self.wander > 0.0 {
+                let step: f64 = ctx.rng.sample(StandardNormal);
+                wander += -wander * pull + step * kick;
+            }
+            let offset = self.ppm + self.drift * seconds + wander;
+            position += 1.0 / (1.0 + offset * 1e-6).max(MIN_CLOCK_RATIO);
+        }
+        ctx.time_map.push(time_map);
+    }
+}
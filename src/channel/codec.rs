@@ -0,0 +1,281 @@
+use super::{ChannelContext, ChannelStage};
+use crate::resample;
+
+/// Bias added to the magnitude before mu-law companding
+const MULAW_BIAS: i32 = 0x84;
+
+/// Largest magnitude that mu-law can represent, once the bias is added
+const MULAW_CLIP: i32 = 32635;
+
+/// G.711 mu-law, as used on North American and Japanese phone networks
+fn mulaw_encode(sample: i16) -> u8 {
+    let mut magnitude = sample as i32;
+    let sign = if magnitude < 0 {
+        magnitude = -magnitude;
+        0x80
+    } else {
+        0x00
+    };
+    magnitude = magnitude.min(MULAW_CLIP) + MULAW_BIAS;
+    let exponent = (31 - (magnitude as u32).leading_zeros()) as i32 - 7;
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) | mantissa) as u8
+}
+
+fn mulaw_decode(code: u8) -> i16 {
+    let code = !code as i32;
+    let exponent = (code >> 4) & 0x07;
+    let mantissa = code & 0x0f;
+    let magnitude = (((mantissa << 3) + MULAW_BIAS) << exponent) - MULAW_BIAS;
+    if code & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// Upper end of each A-law segment, on the 13-bit scale
+const ALAW_SEGMENT_END: [i32; 8] = [0x1f, 0x3f, 0x7f, 0xff, 0x1ff, 0x3ff, 0x7ff, 0xfff];
+
+/// G.711 A-law, as used on European phone networks
+fn alaw_encode(sample: i16) -> u8 {
+    let mut value = (sample as i32) >> 3;
+    let mask = if value >= 0 {
+        0xd5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    let segment = ALAW_SEGMENT_END
+        .iter()
+        .position(|end| value <= *end)
+        .unwrap_or(8) as i32;
+    if segment >= 8 {
+        return (0x7f ^ mask) as u8;
+    }
+    let mantissa = if segment < 2 {
+        (value >> 1) & 0x0f
+    } else {
+        (value >> segment) & 0x0f
+    };
+    (((segment << 4) | mantissa) ^ mask) as u8
+}
+
+fn alaw_decode(code: u8) -> i16 {
+    let code = (code ^ 0x55) as i32;
+    let segment = (code & 0x70) >> 4;
+    let mut magnitude = (code & 0x0f) << 4;
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+    if code & 0x80 != 0 {
+        magnitude as i16
+    } else {
+        -magnitude as i16
+    }
+}
+
+/// How much the IMA ADPCM step index changes for each magnitude code
+const ADPCM_INDEX_ADJUST: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// IMA ADPCM quantizer step sizes
+const ADPCM_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// IMA ADPCM, a 4-bit codec found in VoIP gateways and DECT phones.  The
+/// decoder tracks exactly the same prediction as the encoder, so encoding
+/// a sample gives the decoded value directly.
+struct Adpcm {
+    predictor: i32,
+    index: usize,
+}
+
+impl Adpcm {
+    fn new() -> Adpcm {
+        Adpcm {
+            predictor: 0,
+            index: 0,
+        }
+    }
+
+    fn encode_decode(&mut self, sample: i16) -> i16 {
+        let step = ADPCM_STEPS[self.index];
+        let mut diff = sample as i32 - self.predictor;
+        let mut code = 0;
+        if diff < 0 {
+            code = 8;
+            diff = -diff;
+        }
+        let mut delta = step >> 3;
+        if diff >= step {
+            code |= 4;
+            diff -= step;
+            delta += step;
+        }
+        if diff >= step >> 1 {
+            code |= 2;
+            diff -= step >> 1;
+            delta += step >> 1;
+        }
+        if diff >= step >> 2 {
+            code |= 1;
+            delta += step >> 2;
+        }
+        if code & 8 != 0 {
+            self.predictor -= delta;
+        } else {
+            self.predictor += delta;
+        }
+        self.predictor = self.predictor.clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index as i32 + ADPCM_INDEX_ADJUST[code & 7]).clamp(0, 88) as usize;
+        self.predictor as i16
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CodecType {
+    MuLaw,
+    ALaw,
+    Adpcm,
+}
+
+impl CodecType {
+    pub fn parse(name: &str) -> Option<CodecType> {
+        match name {
+            "mulaw" => Some(CodecType::MuLaw),
+            "alaw" => Some(CodecType::ALaw),
+            "adpcm" => Some(CodecType::Adpcm),
+            _ => None,
+        }
+    }
+}
+
+/// A voice codec in the audio path, such as on a phone call.  The signal is
+/// resampled to the codec's rate, encoded and decoded, and then resampled
+/// back again.
+pub struct Codec {
+    codec: CodecType,
+
+    /// Sample rate of the codec, or None to run at the channel's rate
+    rate: Option<f64>,
+}
+
+impl Codec {
+    pub fn new(codec: CodecType, rate: Option<f64>) -> Codec {
+        Codec { codec, rate }
+    }
+}
+
+impl ChannelStage for Codec {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let len = samples.len();
+        let rate = self.rate.unwrap_or(ctx.sample_rate);
+        let mut adpcm = Adpcm::new();
+        let decoded: Vec<f64> = resample::resample(samples, ctx.sample_rate, rate)
+            .iter()
+            .map(|sample| {
+                let pcm = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+                let pcm = match self.codec {
+                    CodecType::MuLaw => mulaw_decode(mulaw_encode(pcm)),
+                    CodecType::ALaw => alaw_decode(alaw_encode(pcm)),
+                    CodecType::Adpcm => adpcm.encode_decode(pcm),
+                };
+                pcm as f64 / 32767.0
+            })
+            .collect();
+        *samples = resample::resample(&decoded, rate, ctx.sample_rate);
+        samples.resize(len, 0.0);
+    }
+
+    fn bandwidth(&self) -> Option<f64> {
+        self.rate.map(|rate| rate / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mulaw_matches_g711() {
+        // Codes and their 16-bit values from the G.711 mu-law table
+        for (code, value) in [
+            (0xff, 0),
+            (0xfe, 8),
+            (0xef, 132),
+            (0x80, 32124),
+            (0x7e, -8),
+            (0x00, -32124),
+        ] {
+            assert_eq!(mulaw_decode(code), value, "code {:#04x}", code);
+            assert_eq!(mulaw_encode(value), code, "value {}", value);
+        }
+        assert_eq!(mulaw_encode(i16::MAX), 0x80);
+        assert_eq!(mulaw_encode(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn alaw_matches_g711() {
+        // Codes and their 16-bit values from the G.711 A-law table
+        for (code, value) in [
+            (0xd5, 8),
+            (0xd4, 24),
+            (0xc5, 264),
+            (0xaa, 32256),
+            (0x55, -8),
+            (0x2a, -32256),
+        ] {
+            assert_eq!(alaw_decode(code), value, "code {:#04x}", code);
+            assert_eq!(alaw_encode(value), code, "value {}", value);
+        }
+        assert_eq!(alaw_encode(0), 0xd5);
+        assert_eq!(alaw_encode(i16::MAX), 0xaa);
+        assert_eq!(alaw_encode(i16::MIN), 0x2a);
+    }
+
+    #[test]
+    fn companding_round_trip_stays_within_a_step() {
+        // Each segment's step is about a sixteenth of the values within it
+        for sample in (i16::MIN..=i16::MAX).step_by(7) {
+            let exact = sample as i32;
+            let limit = exact.abs() / 16 + 16;
+            let mulaw = mulaw_decode(mulaw_encode(sample)) as i32;
+            assert!((mulaw - exact).abs() <= limit, "mu-law {}", sample);
+            let alaw = alaw_decode(alaw_encode(sample)) as i32;
+            assert!((alaw - exact).abs() <= limit, "A-law {}", sample);
+        }
+    }
+
+    #[test]
+    fn adpcm_follows_ima_reference() {
+        // A step up to 1000 and back down, worked through by hand with the
+        // IMA ADPCM step table and index adjustments
+        let mut adpcm = Adpcm::new();
+        let decoded: Vec<i16> = [1000, 1000, 1000, 1000, 1000, 1000, 0, 0, 0]
+            .iter()
+            .map(|sample| adpcm.encode_decode(*sample))
+            .collect();
+        assert_eq!(decoded, [11, 41, 104, 240, 533, 996, 71, -61, 59]);
+    }
+
+    #[test]
+    fn adpcm_tracks_a_tone() {
+        let mut adpcm = Adpcm::new();
+        let mut worst = 0;
+        for n in 0..8000 {
+            let sample = (8000.0 * (n as f64 * 0.05).sin()) as i16;
+            let decoded = adpcm.encode_decode(sample);
+            if n > 100 {
+                worst = worst.max((decoded as i32 - sample as i32).abs());
+            }
+        }
+        assert!(worst < 400, "worst error {}", worst);
+    }
+}
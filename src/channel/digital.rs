@@ -0,0 +1,103 @@
+use super::{ChannelContext, ChannelStage};
+use crate::{dsp, resample};
+
+/// Pass the signal through a link running at a different sample rate, by
+/// converting to that rate and back again
+pub struct Resample {
+    rate: f64,
+}
+
+impl Resample {
+    pub fn new(rate: f64) -> Resample {
+        Resample { rate }
+    }
+}
+
+impl ChannelStage for Resample {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let len = samples.len();
+        let reduced = resample::resample(samples, ctx.sample_rate, self.rate);
+        *samples = resample::resample(&reduced, self.rate, ctx.sample_rate);
+        samples.resize(len, 0.0);
+    }
+
+    fn bandwidth(&self) -> Option<f64> {
+        Some(self.rate / 2.0)
+    }
+}
+
+/// Round every sample to the nearest level of a converter with this many
+/// bits spanning [-1, 1]
+pub struct Quantize {
+    step: f64,
+}
+
+impl Quantize {
+    pub fn new(bits: f64) -> Quantize {
+        Quantize {
+            step: 2.0 / 2f64.powf(bits),
+        }
+    }
+}
+
+impl ChannelStage for Quantize {
+    fn process(&mut self, samples: &mut Vec<f64>, _ctx: &mut ChannelContext) {
+        for sample in samples.iter_mut() {
+            *sample = (*sample / self.step).round() * self.step;
+        }
+    }
+}
+
+/// The receiver's analog-to-digital converter.  The signal picks up a gain
+/// error and a DC offset on the way in, saturates at full scale, and is
+/// rounded to `bits` of resolution.  The levels reach the demodulator on
+/// the same ±32767 scale as any other signal, so each can be a code away
+/// from what a narrower ADC would deliver left-justified in 16 bits.
+pub struct Adc {
+    bits: f64,
+
+    /// Offset added to every sample, as a fraction of full scale
+    dc_offset: f64,
+    gain_error_db: f64,
+    dither: dsp::Dither,
+
+    /// Percentage of samples that hit full scale in the last run
+    clipped_percent: f64,
+}
+
+impl Adc {
+    pub fn new(bits: f64, dc_offset: f64, gain_error_db: f64, dither: dsp::Dither) -> Adc {
+        Adc {
+            bits,
+            dc_offset,
+            gain_error_db,
+            dither,
+            clipped_percent: 0.0,
+        }
+    }
+}
+
+impl ChannelStage for Adc {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let step = 2.0 / 2f64.powf(self.bits);
+        let gain = dsp::db_to_gain(self.gain_error_db);
+        let mut clipped = 0;
+        for sample in samples.iter_mut() {
+            let input = *sample * gain + self.dc_offset;
+            if input.abs() >= 1.0 {
+                clipped += 1;
+            }
+            let code = (input / step + self.dither.sample(ctx.rng)).round();
+            *sample = (code * step).clamp(-1.0, 1.0 - step);
+        }
+        self.clipped_percent = clipped as f64 * 100.0 / samples.len().max(1) as f64;
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["clipped_percent"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.clipped_percent]
+    }
+}
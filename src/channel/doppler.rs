@@ -0,0 +1,70 @@
+use std::f64::consts::PI;
+
+use super::{ChannelContext, ChannelStage};
+use crate::dsp::{self, Complex};
+
+/// Speed of sound in air at room temperature, in metres per second
+const SPEED_OF_SOUND: f64 = 343.0;
+
+/// Shift every frequency in the signal by a fixed offset plus the Doppler
+/// shift of a transmitter moving towards the receiver.  The transmitter
+/// moves at `velocity` metres per second, swaying back and forth by a
+/// further `sway` m/s at `sway_hz`, like a phone held in a moving hand.
+///
+/// Doppler shift is proportional to frequency, so it is calculated at the
+/// centre of the tone pair.  F_LO and F_HI are close enough together that
+/// the error in each is a small fraction of the shift.
+pub struct Doppler {
+    offset: f64,
+    velocity: f64,
+    sway: f64,
+    sway_hz: f64,
+
+    /// Largest shift applied, in Hz
+    peak_shift: f64,
+}
+
+impl Doppler {
+    pub fn new(offset: f64, velocity: f64, sway: f64, sway_hz: f64) -> Doppler {
+        Doppler {
+            offset,
+            velocity,
+            sway,
+            sway_hz,
+            peak_shift: 0.0,
+        }
+    }
+
+    fn shift(&self, seconds: f64, centre: f64) -> f64 {
+        let velocity = self.velocity + self.sway * (2.0 * PI * self.sway_hz * seconds).sin();
+        self.offset + centre * velocity / SPEED_OF_SOUND
+    }
+}
+
+impl ChannelStage for Doppler {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        self.peak_shift = 0.0;
+        if self.offset == 0.0 && self.velocity == 0.0 && self.sway == 0.0 {
+            return;
+        }
+        let centre = (ctx.f_lo + ctx.f_hi) / 2.0;
+        let analytic = dsp::analytic_signal(samples);
+        let mut phase: f64 = 0.0;
+        for (n, (sample, value)) in samples.iter_mut().zip(analytic).enumerate() {
+            let shift = self.shift(n as f64 / ctx.sample_rate, centre);
+            if shift.abs() > self.peak_shift.abs() {
+                self.peak_shift = shift;
+            }
+            *sample = (value * Complex::new(phase.cos(), phase.sin())).re;
+            phase = (phase + 2.0 * PI * shift / ctx.sample_rate) % (2.0 * PI);
+        }
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["peak_shift"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.peak_shift]
+    }
+}
@@ -0,0 +1,121 @@
+use rand::Rng;
+
+use super::{ChannelContext, ChannelStage};
+
+/// How the length of each dropout varies around the mean
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthDistribution {
+    /// Every dropout is exactly the mean length
+    Fixed,
+
+    /// Anywhere from zero to twice the mean length
+    Uniform,
+
+    /// Mostly short, with the occasional long gap
+    Exponential,
+}
+
+impl LengthDistribution {
+    pub fn parse(name: &str) -> Option<LengthDistribution> {
+        match name {
+            "fixed" => Some(LengthDistribution::Fixed),
+            "uniform" => Some(LengthDistribution::Uniform),
+            "exponential" => Some(LengthDistribution::Exponential),
+            _ => None,
+        }
+    }
+
+    fn sample(self, mean: f64, rng: &mut dyn rand::RngCore) -> f64 {
+        match self {
+            LengthDistribution::Fixed => mean,
+            LengthDistribution::Uniform => rng.gen_range(0.0, 2.0 * mean),
+            LengthDistribution::Exponential => -rng.gen_range(f64::EPSILON, 1.0).ln() * mean,
+        }
+    }
+}
+
+/// When dropouts happen
+#[derive(Clone, Copy, Debug)]
+pub enum Schedule {
+    /// At random, with this average number per second
+    Random(f64),
+
+    /// Every `interval` seconds, starting at `start` seconds
+    Every { interval: f64, start: f64 },
+}
+
+/// Gaps where the signal is lost entirely, such as when the operating
+/// system fails to deliver an audio buffer in time
+pub struct Dropout {
+    schedule: Schedule,
+
+    /// Average length of each dropout, in milliseconds
+    length_msecs: f64,
+    distribution: LengthDistribution,
+
+    /// Number of dropouts in the last run
+    count: usize,
+
+    /// Total time lost in the last run, in milliseconds
+    dropped_msecs: f64,
+}
+
+impl Dropout {
+    pub fn new(schedule: Schedule, length_msecs: f64, distribution: LengthDistribution) -> Dropout {
+        Dropout {
+            schedule,
+            length_msecs,
+            distribution,
+            count: 0,
+            dropped_msecs: 0.0,
+        }
+    }
+
+    /// Time from one dropout to the next, in seconds
+    fn interval(&self, rng: &mut dyn rand::RngCore) -> Option<f64> {
+        match self.schedule {
+            // Random dropouts form a Poisson process, so the time between
+            // them is exponentially distributed.
+            Schedule::Random(rate) if rate > 0.0 => {
+                Some(-rng.gen_range(f64::EPSILON, 1.0).ln() / rate)
+            }
+            Schedule::Every { interval, .. } if interval > 0.0 => Some(interval),
+            _ => None,
+        }
+    }
+}
+
+impl ChannelStage for Dropout {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        self.count = 0;
+        let mut dropped = 0;
+        let mut seconds = match self.schedule {
+            Schedule::Random(_) => 0.0,
+            Schedule::Every { start, interval } => start - interval,
+        };
+        while let Some(interval) = self.interval(ctx.rng) {
+            seconds += interval;
+            let start = (seconds * ctx.sample_rate).max(0.0) as usize;
+            if start >= samples.len() {
+                break;
+            }
+            let length_msecs = self.distribution.sample(self.length_msecs, ctx.rng);
+            let length = (length_msecs * ctx.sample_rate / 1000.0).round() as usize;
+            let end = (start + length).min(samples.len());
+            for sample in samples[start..end].iter_mut() {
+                *sample = 0.0;
+            }
+            self.count += 1;
+            dropped += end - start;
+        }
+        self.dropped_msecs = dropped as f64 * 1000.0 / ctx.sample_rate;
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["count", "dropped_msecs"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.count as f64, self.dropped_msecs]
+    }
+}
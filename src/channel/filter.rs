@@ -0,0 +1,54 @@
+use super::{ChannelContext, ChannelStage};
+use crate::dsp;
+
+/// A fixed change in level
+pub struct Gain {
+    gain: f64,
+}
+
+impl Gain {
+    pub fn new(db: f64) -> Gain {
+        Gain {
+            gain: dsp::db_to_gain(db),
+        }
+    }
+}
+
+impl ChannelStage for Gain {
+    fn process(&mut self, samples: &mut Vec<f64>, _ctx: &mut ChannelContext) {
+        for sample in samples.iter_mut() {
+            *sample *= self.gain;
+        }
+    }
+}
+
+/// A Butterworth filter, with cutoffs in Hz
+pub enum Filter {
+    Lowpass { cutoff: f64, order: usize },
+    Highpass { cutoff: f64, order: usize },
+    Bandpass { low: f64, high: f64, order: usize },
+}
+
+impl ChannelStage for Filter {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let rate = ctx.sample_rate;
+        *samples = match *self {
+            Filter::Lowpass { cutoff, order } => dsp::lowpass_order(samples, rate, cutoff, order),
+            Filter::Highpass { cutoff, order } => dsp::highpass_order(samples, rate, cutoff, order),
+            Filter::Bandpass { low, high, order } => dsp::lowpass_order(
+                &dsp::highpass_order(samples, rate, low, order),
+                rate,
+                high,
+                order,
+            ),
+        };
+    }
+
+    fn bandwidth(&self) -> Option<f64> {
+        match *self {
+            Filter::Lowpass { cutoff, .. } => Some(cutoff),
+            Filter::Highpass { .. } => None,
+            Filter::Bandpass { high, .. } => Some(high),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use super::{ChannelContext, ChannelStage};
+use crate::{dsp, resample};
+
+/// Clipping is done at this multiple of the sample rate, so that the
+/// harmonics it creates are filtered out rather than aliasing back down
+/// into the band, as they would be by a real receiver's anti-aliasing
+/// filter.
+const CLIP_OVERSAMPLING: f64 = 4.0;
+
+/// How a signal that exceeds the clipping threshold is limited
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipMode {
+    /// Flatten everything beyond the threshold, like an overdriven amplifier
+    Hard,
+
+    /// Compress smoothly towards the threshold, like an overdriven speaker
+    Soft,
+}
+
+impl ClipMode {
+    pub fn parse(name: &str) -> Option<ClipMode> {
+        match name {
+            "hard" => Some(ClipMode::Hard),
+            "soft" => Some(ClipMode::Soft),
+            _ => None,
+        }
+    }
+}
+
+/// Clipping at a fraction of full scale
+pub struct Clip {
+    threshold: f64,
+    mode: ClipMode,
+
+    /// Percentage of samples beyond the threshold in the last run
+    clipped_percent: f64,
+}
+
+impl Clip {
+    pub fn new(threshold: f64, mode: ClipMode) -> Clip {
+        Clip {
+            threshold,
+            mode,
+            clipped_percent: 0.0,
+        }
+    }
+}
+
+impl ChannelStage for Clip {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let threshold = self.threshold.max(f64::MIN_POSITIVE);
+        let len = samples.len();
+        let oversampled_rate = ctx.sample_rate * CLIP_OVERSAMPLING;
+        let mut oversampled = resample::resample(samples, ctx.sample_rate, oversampled_rate);
+        let mut clipped = 0;
+        for sample in oversampled.iter_mut() {
+            if sample.abs() > threshold {
+                clipped += 1;
+            }
+            *sample = match self.mode {
+                ClipMode::Hard => sample.clamp(-threshold, threshold),
+                ClipMode::Soft => threshold * (*sample / threshold).tanh(),
+            };
+        }
+        self.clipped_percent = clipped as f64 * 100.0 / oversampled.len().max(1) as f64;
+        *samples = resample::resample(&oversampled, oversampled_rate, ctx.sample_rate);
+        samples.resize(len, 0.0);
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["clipped_percent"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.clipped_percent]
+    }
+}
+
+/// The way the level changes over time during a fade
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FadeShape {
+    /// Smoothly up and down once every period
+    Sine,
+
+    /// Towards a new random level every period, like a user wandering
+    /// around with the phone
+    Random,
+}
+
+impl FadeShape {
+    pub fn parse(name: &str) -> Option<FadeShape> {
+        match name {
+            "sine" => Some(FadeShape::Sine),
+            "random" => Some(FadeShape::Random),
+            _ => None,
+        }
+    }
+}
+
+/// A slow change in volume, between full level and `depth` dB below it
+pub struct Fade {
+    depth_db: f64,
+    period_secs: f64,
+    shape: FadeShape,
+}
+
+impl Fade {
+    pub fn new(depth_db: f64, period_secs: f64, shape: FadeShape) -> Fade {
+        Fade {
+            depth_db,
+            period_secs,
+            shape,
+        }
+    }
+}
+
+impl ChannelStage for Fade {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        if self.depth_db == 0.0 || self.period_secs <= 0.0 {
+            return;
+        }
+        let period = (self.period_secs * ctx.sample_rate).max(1.0);
+        // Attenuation in dB at the start and end of the current period
+        let mut from = 0.0;
+        let mut to = match self.shape {
+            FadeShape::Sine => 0.0,
+            FadeShape::Random => ctx.rng.gen_range(0.0, 1.0) * self.depth_db,
+        };
+        for (n, sample) in samples.iter_mut().enumerate() {
+            let phase = (n as f64 % period) / period;
+            if n > 0 && phase < 1.0 / period && self.shape == FadeShape::Random {
+                from = to;
+                to = ctx.rng.gen_range(0.0, 1.0) * self.depth_db;
+            }
+            let attenuation = match self.shape {
+                FadeShape::Sine => self.depth_db * (0.5 - 0.5 * (2.0 * PI * phase).cos()),
+                FadeShape::Random => from + (to - from) * (0.5 - 0.5 * (PI * phase).cos()),
+            };
+            *sample *= dsp::db_to_gain(-attenuation);
+        }
+    }
+}
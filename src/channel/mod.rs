@@ -0,0 +1,783 @@
+mod background;
+mod clock;
+mod codec;
+mod digital;
+mod doppler;
+mod dropout;
+mod filter;
+mod level;
+mod noise;
+mod recording;
+mod reverb;
+mod room;
+mod transducer;
+
+pub use transducer::profile_names;
+
+use std::sync::Arc;
+
+use rand::RngCore;
+
+use crate::steppedrange::{SteppedRange, Unit};
+use recording::Recording;
+
+/// Everything a stage may need to know about the signal passing through it
+pub struct ChannelContext<'a> {
+    pub sample_rate: f64,
+
+    /// Symbols per second actually sent over the air
+    pub baud_rate: f64,
+
+    /// Tones actually sent over the air
+    pub f_lo: f64,
+    pub f_hi: f64,
+    pub rng: &'a mut dyn RngCore,
+
+    /// Where each transmitted sample has ended up, for stages that change
+    /// the timing of the waveform
+    pub time_map: TimeMap,
+}
+
+/// How stages have moved the waveform in time, such as a receiver whose
+/// clock runs fast.  Each stage that changes the timing adds pairs of input
+/// and output sample positions, in order.  Positions between pairs are
+/// interpolated, and positions beyond the last pair are extrapolated from
+/// the last two.
+#[derive(Clone, Debug, Default)]
+pub struct TimeMap {
+    stages: Vec<Vec<(f64, f64)>>,
+}
+
+impl TimeMap {
+    /// Add the timing of a stage, which is applied after any added before
+    pub fn push(&mut self, points: Vec<(f64, f64)>) {
+        if !points.is_empty() {
+            self.stages.push(points);
+        }
+    }
+
+    /// The position in the received waveform of transmitted sample `sample`
+    pub fn map(&self, sample: f64) -> f64 {
+        self.stages.iter().fold(sample, |position, points| {
+            let next = points
+                .partition_point(|(input, _)| *input <= position)
+                .clamp(1, points.len().max(2) - 1);
+            match (points.get(next - 1), points.get(next)) {
+                (Some((x0, y0)), Some((x1, y1))) => y0 + (position - x0) * (y1 - y0) / (x1 - x0),
+                (Some((x0, y0)), None) => y0 + position - x0,
+                _ => position,
+            }
+        })
+    }
+}
+
+/// One step of the simulated channel between the transmitter and the
+/// receiver, such as a filter or a source of noise.
+pub trait ChannelStage {
+    /// Apply this stage's impairment to `samples` in place
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext);
+
+    /// Names of any quantities this stage measures while processing, which
+    /// are reported alongside the parameters
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The value of each measurement from the last call to `process()`
+    fn measurements(&self) -> Vec<f64> {
+        vec![]
+    }
+
+    /// The highest frequency this stage lets through, if it is band-limited
+    fn bandwidth(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// The kinds of stage that may be used, along with each parameter they
+/// accept and its default value
+const STAGES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "noise",
+        &[("level", "none"), ("snr", "none"), ("ebn0", "none")],
+    ),
+    ("interferer", &[("freq", "10000"), ("level", "0.1")]),
+    ("gain", &[("db", "0")]),
+    ("lowpass", &[("cutoff", "16000"), ("order", "4")]),
+    ("highpass", &[("cutoff", "4000"), ("order", "4")]),
+    (
+        "bandpass",
+        &[("low", "4000"), ("high", "16000"), ("order", "4")],
+    ),
+    ("resample", &[("rate", "32000")]),
+    (
+        "dropout",
+        &[
+            ("rate", "1"),
+            ("length", "10"),
+            ("distribution", "fixed"),
+            ("every", "0"),
+            ("start", "0"),
+        ],
+    ),
+    (
+        "background",
+        &[("path", "none"), ("snr", "10"), ("offset", "random")],
+    ),
+    (
+        "transducer",
+        &[("speaker", "none"), ("microphone", "none"), ("taps", "255")],
+    ),
+    ("clip", &[("threshold", "0.5"), ("mode", "hard")]),
+    (
+        "fade",
+        &[("depth", "6"), ("period", "2"), ("shape", "sine")],
+    ),
+    ("reverb", &[("rt60", "0.3"), ("mix", "0.2")]),
+    ("quantize", &[("bits", "8")]),
+    ("codec", &[("type", "mulaw"), ("rate", "8000")]),
+    (
+        "adc",
+        &[
+            ("bits", "12"),
+            ("dc", "0"),
+            ("gain_error", "0"),
+            ("dither", "none"),
+        ],
+    ),
+    ("ir", &[("path", "none"), ("mix", "1")]),
+    (
+        "clock",
+        &[
+            ("ppm", "0"),
+            ("drift", "0"),
+            ("wander", "0"),
+            ("wander_secs", "1"),
+        ],
+    ),
+    (
+        "doppler",
+        &[
+            ("offset", "0"),
+            ("velocity", "0"),
+            ("sway", "0"),
+            ("sway_hz", "1"),
+        ],
+    ),
+    (
+        "room",
+        &[
+            ("rt60", "0.5"),
+            ("reflections", "8"),
+            ("drr", "0"),
+            ("seed", "0"),
+            ("mix", "1"),
+        ],
+    ),
+];
+
+/// Parameters whose values are wave files.  Each file is read once when the
+/// stage is parsed and shared by every variant of the stage.
+const RECORDINGS: &[(&str, &str)] = &[("ir", "path"), ("background", "path")];
+
+/// Parameters whose values are whole numbers.  These are kept exactly, so
+/// that a seed above 2^53 isn't rounded on its way through a float.
+const INTEGERS: &[(&str, &str)] = &[
+    ("lowpass", "order"),
+    ("highpass", "order"),
+    ("bandpass", "order"),
+    ("transducer", "taps"),
+    ("room", "reflections"),
+    ("room", "seed"),
+];
+
+/// Names of every kind of stage, in the order they were added
+pub fn stage_names() -> Vec<&'static str> {
+    STAGES.iter().map(|(name, _)| *name).collect()
+}
+
+pub enum ChannelError {
+    UnknownStage(String),
+    UnknownParameter(String, String),
+    UnparseableStage(String),
+    BadValue(String, String),
+    ConflictingParameters(String, &'static [&'static str]),
+    Io(String, std::io::Error),
+}
+
+impl core::fmt::Debug for ChannelError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            ChannelError::UnknownStage(s) => write!(f, "unknown channel stage {}", s),
+            ChannelError::UnknownParameter(kind, param) => {
+                write!(f, "{} has no parameter named {}", kind, param)
+            }
+            ChannelError::UnparseableStage(s) => write!(f, "unable to parse channel stage {}", s),
+            ChannelError::BadValue(param, value) => {
+                write!(f, "invalid value {} for {}", value, param)
+            }
+            ChannelError::ConflictingParameters(kind, params) => {
+                write!(f, "{} needs exactly one of {}", kind, params.join(", "))
+            }
+            ChannelError::Io(path, e) => write!(f, "unable to read {}: {:?}", path, e),
+        }
+    }
+}
+
+/// Expand a parameter value into every value it covers.  The value is a list
+/// separated by commas, where a directory stands for every wave file inside
+/// of it, an item that parses as a `SteppedRange` stands for every number in
+/// the range, and anything else, such as `48k-hall.wav`, is taken as a
+/// single value.  Ranges of an `integer` parameter only cover whole numbers.
+fn expand_value(value: &str, integer: bool) -> Result<Vec<String>, ChannelError> {
+    let mut values = vec![];
+    for item in value.split(',').map(str::trim) {
+        values.extend(expand_item(item, integer)?);
+    }
+    Ok(values)
+}
+
+fn expand_item(value: &str, integer: bool) -> Result<Vec<String>, ChannelError> {
+    let path = std::path::Path::new(value);
+    if path.is_dir() {
+        let mut files = vec![];
+        for entry in path
+            .read_dir()
+            .map_err(|e| ChannelError::Io(value.to_owned(), e))?
+        {
+            let entry = entry.map_err(|e| ChannelError::Io(value.to_owned(), e))?;
+            let name = entry.path();
+            let is_wav = name
+                .extension()
+                .map(|extension| extension.eq_ignore_ascii_case("wav"))
+                .unwrap_or(false);
+            if is_wav {
+                files.push(name.to_string_lossy().into_owned());
+            }
+        }
+        if files.is_empty() {
+            return Err(ChannelError::BadValue(
+                "directory".to_owned(),
+                value.to_owned(),
+            ));
+        }
+        files.sort();
+        return Ok(files);
+    }
+    let range = if integer {
+        SteppedRange::parse_integer(value, Unit::None)
+    } else {
+        SteppedRange::parse(value)
+    };
+    match range {
+        Ok(range) => Ok(range.into_iter().map(|v| v.to_string()).collect()),
+        Err(_) => Ok(vec![value.to_owned()]),
+    }
+}
+
+/// A stage as given on the command line, where each parameter may have
+/// several values to sweep over.
+#[derive(Clone, Debug)]
+pub struct StageSpec {
+    kind: String,
+    params: Vec<(String, Vec<String>)>,
+
+    /// Every wave file named by the parameters, by path
+    recordings: Vec<(String, Arc<Recording>)>,
+}
+
+impl StageSpec {
+    /// Parse a stage in the form `KIND:PARAM=VALUE,PARAM=VALUE,...`.
+    /// Parameters that aren't mentioned take their default value.
+    pub fn parse(input: &str) -> Result<StageSpec, ChannelError> {
+        let mut fields = input.splitn(2, ':');
+        let kind = fields.next().unwrap_or("").trim();
+        let defaults = STAGES
+            .iter()
+            .find(|(name, _)| *name == kind)
+            .map(|(_, params)| *params)
+            .ok_or_else(|| ChannelError::UnknownStage(kind.to_owned()))?;
+        let mut params: Vec<(String, Vec<String>)> = defaults
+            .iter()
+            .map(|(name, value)| (name.to_string(), vec![value.to_string()]))
+            .collect();
+        // A setting without an `=` is another item in the previous setting's
+        // list, as in `snr=0,5,10`
+        let mut settings: Vec<(&str, String)> = vec![];
+        for setting in fields.next().unwrap_or("").split(',') {
+            let setting = setting.trim();
+            if setting.is_empty() {
+                continue;
+            }
+            let mut pair = setting.splitn(2, '=');
+            let name = pair.next().unwrap_or("").trim();
+            match (pair.next(), settings.last_mut()) {
+                (Some(value), _) => settings.push((name, value.trim().to_owned())),
+                (None, Some((_, value))) => {
+                    value.push(',');
+                    value.push_str(setting);
+                }
+                (None, None) => return Err(ChannelError::UnparseableStage(input.to_owned())),
+            }
+        }
+        for (name, value) in settings {
+            let param = params
+                .iter_mut()
+                .find(|(param, _)| param == name)
+                .ok_or_else(|| ChannelError::UnknownParameter(kind.to_owned(), name.to_owned()))?;
+            param.1 = expand_value(&value, INTEGERS.contains(&(kind, name)))?;
+        }
+        let mut recordings = vec![];
+        for (name, values) in &params {
+            if RECORDINGS.contains(&(kind, name.as_str())) {
+                for path in values {
+                    let recording =
+                        Recording::load(path).map_err(|e| ChannelError::Io(path.clone(), e))?;
+                    recordings.push((path.clone(), Arc::new(recording)));
+                }
+            }
+        }
+        Ok(StageSpec {
+            kind: kind.to_owned(),
+            params,
+            recordings,
+        })
+    }
+
+    /// Shorthand for a stage with a single value for each of the given
+    /// parameters, as used by the older command line options.
+    pub fn with_values(kind: &str, values: &[(&str, f64)]) -> Result<StageSpec, ChannelError> {
+        let settings: Vec<String> = values
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        StageSpec::parse(&format!("{}:{}", kind, settings.join(",")))
+    }
+
+    /// Every combination of this stage's parameter values
+    fn variants(&self) -> Vec<StageConfig> {
+        let mut variants = vec![StageConfig {
+            kind: self.kind.clone(),
+            params: vec![],
+            recordings: self.recordings.clone(),
+        }];
+        for (name, values) in &self.params {
+            variants = variants
+                .iter()
+                .flat_map(|variant| {
+                    values.iter().map(move |value| {
+                        let mut variant = variant.clone();
+                        variant.params.push((name.clone(), value.clone()));
+                        variant
+                    })
+                })
+                .collect();
+        }
+        variants
+    }
+}
+
+/// A single stage with exactly one value for each parameter
+#[derive(Clone, Debug)]
+pub struct StageConfig {
+    kind: String,
+    params: Vec<(String, String)>,
+    recordings: Vec<(String, Arc<Recording>)>,
+}
+
+impl StageConfig {
+    fn text(&self, name: &str) -> &str {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or("")
+    }
+
+    fn float(&self, name: &str) -> Result<f64, ChannelError> {
+        let value = self.text(name);
+        value.parse::<f64>().map_err(|_| {
+            ChannelError::BadValue(format!("{}.{}", self.kind, name), value.to_owned())
+        })
+    }
+
+    /// A parameter listed in `INTEGERS`
+    fn integer<T: std::str::FromStr>(&self, name: &str) -> Result<T, ChannelError> {
+        let value = self.text(name);
+        value.parse::<T>().map_err(|_| {
+            ChannelError::BadValue(format!("{}.{}", self.kind, name), value.to_owned())
+        })
+    }
+
+    /// The wave file named by a parameter listed in `RECORDINGS`
+    fn recording(&self, name: &str) -> Result<Arc<Recording>, ChannelError> {
+        let path = self.text(name);
+        self.recordings
+            .iter()
+            .find(|(recording, _)| recording == path)
+            .map(|(_, recording)| recording.clone())
+            .ok_or_else(|| {
+                ChannelError::BadValue(format!("{}.{}", self.kind, name), path.to_owned())
+            })
+    }
+
+    /// Parse a parameter that takes one of a fixed set of names
+    fn parse_text<T>(&self, name: &str, parse: fn(&str) -> Option<T>) -> Result<T, ChannelError> {
+        let value = self.text(name);
+        parse(value).ok_or_else(|| {
+            ChannelError::BadValue(format!("{}.{}", self.kind, name), value.to_owned())
+        })
+    }
+
+    /// The single parameter out of `names` that has been given a value,
+    /// for stages that can be configured in several ways
+    fn choose(&self, names: &'static [&'static str]) -> Result<&'static str, ChannelError> {
+        let given: Vec<&'static str> = names
+            .iter()
+            .filter(|name| self.text(name) != "none")
+            .copied()
+            .collect();
+        match given.as_slice() {
+            [name] => Ok(name),
+            _ => Err(ChannelError::ConflictingParameters(
+                self.kind.clone(),
+                names,
+            )),
+        }
+    }
+
+    fn build(&self) -> Result<Box<dyn ChannelStage>, ChannelError> {
+        Ok(match self.kind.as_str() {
+            "noise" => {
+                let level = match self.choose(&["level", "snr", "ebn0"])? {
+                    "level" => noise::NoiseLevel::Deviation(self.float("level")?),
+                    "snr" => noise::NoiseLevel::Snr(self.float("snr")?),
+                    _ => noise::NoiseLevel::EbN0(self.float("ebn0")?),
+                };
+                Box::new(noise::Noise::new(level))
+            }
+            "interferer" => Box::new(noise::Interferer::new(
+                self.float("freq")?,
+                self.float("level")?,
+            )),
+            "gain" => Box::new(filter::Gain::new(self.float("db")?)),
+            "lowpass" => Box::new(filter::Filter::Lowpass {
+                cutoff: self.float("cutoff")?,
+                order: self.integer("order")?,
+            }),
+            "highpass" => Box::new(filter::Filter::Highpass {
+                cutoff: self.float("cutoff")?,
+                order: self.integer("order")?,
+            }),
+            "bandpass" => Box::new(filter::Filter::Bandpass {
+                low: self.float("low")?,
+                high: self.float("high")?,
+                order: self.integer("order")?,
+            }),
+            "resample" => Box::new(digital::Resample::new(self.float("rate")?)),
+            "dropout" => {
+                let every = self.float("every")?;
+                let schedule = if every > 0.0 {
+                    dropout::Schedule::Every {
+                        interval: every,
+                        start: self.float("start")?,
+                    }
+                } else {
+                    dropout::Schedule::Random(self.float("rate")?)
+                };
+                Box::new(dropout::Dropout::new(
+                    schedule,
+                    self.float("length")?,
+                    self.parse_text("distribution", dropout::LengthDistribution::parse)?,
+                ))
+            }
+            "background" => {
+                let recording = self.recording("path")?;
+                let path = recording.path().to_owned();
+                let offset = match self.text("offset") {
+                    "random" => background::Offset::Random,
+                    _ => background::Offset::Seconds(self.float("offset")?),
+                };
+                Box::new(
+                    background::Background::new(recording, self.float("snr")?, offset)
+                        .map_err(|e| ChannelError::Io(path, e))?,
+                )
+            }
+            "transducer" => {
+                let mut responses = vec![];
+                for name in &["speaker", "microphone"] {
+                    let value = self.text(name);
+                    if value != "none" {
+                        responses.push(
+                            transducer::load_response(value)
+                                .map_err(|e| ChannelError::Io(value.to_owned(), e))?,
+                        );
+                    }
+                }
+                Box::new(transducer::Transducer::new(
+                    responses,
+                    self.integer("taps")?,
+                ))
+            }
+            "clip" => Box::new(level::Clip::new(
+                self.float("threshold")?,
+                self.parse_text("mode", level::ClipMode::parse)?,
+            )),
+            "fade" => Box::new(level::Fade::new(
+                self.float("depth")?,
+                self.float("period")?,
+                self.parse_text("shape", level::FadeShape::parse)?,
+            )),
+            "reverb" => Box::new(reverb::Reverb::new(self.float("rt60")?, self.float("mix")?)),
+            "quantize" => Box::new(digital::Quantize::new(self.float("bits")?)),
+            "codec" => Box::new(codec::Codec::new(
+                self.parse_text("type", codec::CodecType::parse)?,
+                match self.text("rate") {
+                    "none" => None,
+                    _ => Some(self.float("rate")?),
+                },
+            )),
+            "adc" => Box::new(digital::Adc::new(
+                self.float("bits")?,
+                self.float("dc")?,
+                self.float("gain_error")?,
+                self.parse_text("dither", crate::dsp::Dither::parse)?,
+            )),
+            "ir" => {
+                let recording = self.recording("path")?;
+                let path = recording.path().to_owned();
+                Box::new(
+                    room::ImpulseResponse::new(recording, self.float("mix")?)
+                        .map_err(|e| ChannelError::Io(path, e))?,
+                )
+            }
+            "clock" => {
+                // The receiver's clock can't run backwards or stand still
+                let ppm = self.float("ppm")?;
+                if ppm <= -1e6 {
+                    return Err(ChannelError::BadValue(
+                        "clock.ppm".to_owned(),
+                        self.text("ppm").to_owned(),
+                    ));
+                }
+                Box::new(clock::Clock::new(
+                    ppm,
+                    self.float("drift")?,
+                    self.float("wander")?,
+                    self.float("wander_secs")?,
+                ))
+            }
+            "doppler" => Box::new(doppler::Doppler::new(
+                self.float("offset")?,
+                self.float("velocity")?,
+                self.float("sway")?,
+                self.float("sway_hz")?,
+            )),
+            "room" => Box::new(room::Room::new(
+                self.float("rt60")?,
+                self.integer("reflections")?,
+                self.float("drr")?,
+                self.integer("seed")?,
+                self.float("mix")?,
+            )),
+            kind => return Err(ChannelError::UnknownStage(kind.to_owned())),
+        })
+    }
+}
+
+/// A complete chain of stages, run in order on the waveform
+#[derive(Clone, Debug, Default)]
+pub struct Channel {
+    stages: Vec<StageConfig>,
+
+    /// The narrowest bandwidth of any stage, found when the channel is
+    /// checked
+    bandwidth: Option<f64>,
+}
+
+impl Channel {
+    /// Every channel described by `specs`, one for each combination of
+    /// parameter values.  Each channel is checked to make sure that it can
+    /// be built, and the stages' bandwidth is noted while they are.
+    pub fn variants(specs: &[StageSpec]) -> Result<Vec<Channel>, ChannelError> {
+        let mut channels = vec![Channel::default()];
+        for spec in specs {
+            let stages = spec.variants();
+            channels = channels
+                .iter()
+                .flat_map(|channel| {
+                    stages.iter().map(move |stage| {
+                        let mut channel = channel.clone();
+                        channel.stages.push(stage.clone());
+                        channel
+                    })
+                })
+                .collect();
+        }
+        for channel in channels.iter_mut() {
+            for stage in &channel.stages {
+                if let Some(bandwidth) = stage.build()?.bandwidth() {
+                    channel.bandwidth =
+                        Some(channel.bandwidth.map_or(bandwidth, |n| n.min(bandwidth)));
+                }
+            }
+        }
+        Ok(channels)
+    }
+
+    /// A label for each stage.  Stages are named after their kind, with a
+    /// number added when the same kind appears more than once.
+    fn labels(&self) -> Vec<String> {
+        self.stages
+            .iter()
+            .enumerate()
+            .map(|(index, stage)| {
+                let same_kind = |other: &&StageConfig| other.kind == stage.kind;
+                if self.stages.iter().filter(same_kind).count() > 1 {
+                    let occurrence = self.stages[..=index].iter().filter(same_kind).count();
+                    format!("{}{}", stage.kind, occurrence)
+                } else {
+                    stage.kind.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// CSV column names for every parameter of every stage, followed by
+    /// every measurement
+    pub fn columns(&self) -> Vec<String> {
+        let labels = self.labels();
+        let mut columns: Vec<String> = labels
+            .iter()
+            .zip(self.stages.iter())
+            .flat_map(|(label, stage)| {
+                stage
+                    .params
+                    .iter()
+                    .map(move |(name, _)| format!("{}.{}", label, name))
+            })
+            .collect();
+        for (label, stage) in labels.iter().zip(self.stages.iter()) {
+            if let Ok(stage) = stage.build() {
+                for name in stage.measurement_names() {
+                    columns.push(format!("{}.{}", label, name));
+                }
+            }
+        }
+        columns
+    }
+
+    /// The value of each parameter column returned by `columns()`
+    pub fn values(&self) -> Vec<String> {
+        self.stages
+            .iter()
+            .flat_map(|stage| stage.params.iter().map(|(_, value)| value.clone()))
+            .collect()
+    }
+
+    /// A short human-readable description of the channel
+    pub fn describe(&self) -> String {
+        if self.stages.is_empty() {
+            return "none".to_owned();
+        }
+        self.labels()
+            .iter()
+            .zip(self.stages.iter())
+            .map(|(label, stage)| {
+                let params: Vec<String> = stage
+                    .params
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                format!("{}({})", label, params.join(" "))
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// The highest frequency that makes it through every stage, if any of
+    /// them are band-limited
+    pub fn bandwidth(&self) -> Option<f64> {
+        self.bandwidth
+    }
+
+    /// Run `samples` through every stage in order, returning the value of
+    /// each measurement column returned by `columns()`
+    pub fn process(
+        &self,
+        samples: &mut Vec<f64>,
+        ctx: &mut ChannelContext,
+    ) -> Result<Vec<f64>, ChannelError> {
+        let mut measurements = vec![];
+        for stage in &self.stages {
+            let mut stage = stage.build()?;
+            stage.process(samples, ctx);
+            measurements.extend(stage.measurements());
+        }
+        Ok(measurements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_map_interpolates_and_extrapolates() {
+        let mut time_map = TimeMap::default();
+        assert_eq!(time_map.map(10.0), 10.0);
+
+        time_map.push(vec![(0.0, 0.0), (100.0, 50.0), (200.0, 150.0)]);
+        assert_eq!(time_map.map(50.0), 25.0);
+        assert_eq!(time_map.map(150.0), 100.0);
+        assert_eq!(time_map.map(300.0), 250.0);
+        assert_eq!(time_map.map(-10.0), -5.0);
+
+        // Later stages apply to the output of earlier ones
+        time_map.push(vec![(0.0, 10.0)]);
+        assert_eq!(time_map.map(150.0), 110.0);
+    }
+
+    fn values<'a>(spec: &'a StageSpec, name: &str) -> &'a [String] {
+        &spec
+            .params
+            .iter()
+            .find(|(param, _)| param == name)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn numbers_are_ranges_and_names_are_literal() {
+        let spec = StageSpec::parse("noise:snr=0..10..20").unwrap();
+        assert_eq!(values(&spec, "snr"), ["0", "10", "20"]);
+        let spec = StageSpec::parse("transducer:speaker=3inch.csv,microphone=48k-mic.csv").unwrap();
+        assert_eq!(values(&spec, "speaker"), ["3inch.csv"]);
+        assert_eq!(values(&spec, "microphone"), ["48k-mic.csv"]);
+        let spec = StageSpec::parse("codec:type=mulaw,alaw").unwrap();
+        assert_eq!(values(&spec, "type"), ["mulaw", "alaw"]);
+    }
+
+    #[test]
+    fn integers_are_kept_exactly() {
+        let spec = StageSpec::parse("room:seed=18446744073709551615,reflections=0x10").unwrap();
+        let config = &spec.variants()[0];
+        assert_eq!(config.integer::<u64>("seed").unwrap(), u64::MAX);
+        assert_eq!(config.integer::<usize>("reflections").unwrap(), 16);
+
+        let spec = StageSpec::parse("room:seed=9007199254740993..9007199254740995").unwrap();
+        assert_eq!(
+            values(&spec, "seed"),
+            ["9007199254740993", "9007199254740994", "9007199254740995"]
+        );
+
+        let spec = StageSpec::parse("lowpass:order=2.5").unwrap();
+        assert!(spec.variants()[0].build().is_err());
+    }
+
+    #[test]
+    fn clock_rejects_stopped_receiver() {
+        assert!(Channel::variants(&[StageSpec::parse("clock:ppm=-1000000").unwrap()]).is_err());
+        assert!(Channel::variants(&[StageSpec::parse("clock:ppm=-999000").unwrap()]).is_ok());
+    }
+}
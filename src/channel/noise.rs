@@ -0,0 +1,93 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use super::{ChannelContext, ChannelStage};
+use crate::dsp;
+
+/// How loud the noise is
+#[derive(Clone, Copy, Debug)]
+pub enum NoiseLevel {
+    /// Fixed standard deviation, relative to a full-scale signal of 1.0
+    Deviation(f64),
+
+    /// Signal-to-noise ratio in dB, relative to the measured signal power
+    Snr(f64),
+
+    /// Energy per bit over the noise spectral density, in dB
+    EbN0(f64),
+}
+
+/// Zero-mean white Gaussian noise added to every sample
+pub struct Noise {
+    level: NoiseLevel,
+
+    /// Ratio of the signal power to the power of the noise that was
+    /// actually added, in dB
+    measured_snr: f64,
+}
+
+impl Noise {
+    pub fn new(level: NoiseLevel) -> Noise {
+        Noise {
+            level,
+            measured_snr: f64::INFINITY,
+        }
+    }
+}
+
+impl ChannelStage for Noise {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let signal_power = dsp::active_power(samples, ctx.sample_rate);
+        let variance = match self.level {
+            NoiseLevel::Deviation(deviation) => deviation * deviation,
+            NoiseLevel::Snr(snr) => signal_power / dsp::db_to_power(snr),
+            // The noise is white across the whole band up to Nyquist, so a
+            // spectral density of N0 gives a variance of N0 * fs / 2.  Each
+            // bit carries the signal power for 1 / baud seconds.
+            NoiseLevel::EbN0(ebn0) => {
+                signal_power * ctx.sample_rate / (2.0 * ctx.baud_rate * dsp::db_to_power(ebn0))
+            }
+        };
+        if variance <= 0.0 {
+            self.measured_snr = f64::INFINITY;
+            return;
+        }
+        let deviation = variance.sqrt();
+        let mut noise_energy = 0.0;
+        for sample in samples.iter_mut() {
+            let noise: f64 = ctx.rng.sample::<f64, _>(StandardNormal) * deviation;
+            noise_energy += noise * noise;
+            *sample += noise;
+        }
+        let noise_power = noise_energy / samples.len() as f64;
+        self.measured_snr = dsp::power_to_db(signal_power / noise_power);
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["measured_snr"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.measured_snr]
+    }
+}
+
+/// A steady tone at a random phase, such as a nearby piece of equipment
+/// whining away in the same band
+pub struct Interferer {
+    frequency: f64,
+    level: f64,
+}
+
+impl Interferer {
+    pub fn new(frequency: f64, level: f64) -> Interferer {
+        Interferer { frequency, level }
+    }
+}
+
+impl ChannelStage for Interferer {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let phase = ctx.rng.gen_range(0.0, 2.0 * std::f64::consts::PI);
+        dsp::add_tone(samples, ctx.sample_rate, self.frequency, self.level, phase);
+    }
+}
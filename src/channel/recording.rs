@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{resample, wav};
+
+/// A wave file used by a stage, such as an impulse response or a background
+/// recording.  It's read once when the channel is parsed and resampled once
+/// for each rate it's needed at, rather than every time a stage is built.
+pub struct Recording {
+    path: String,
+    samples: Vec<f64>,
+    rate: f64,
+
+    /// The largest magnitude of any sample
+    peak: f64,
+
+    /// The recording at each sample rate it's been needed at so far
+    resampled: Mutex<Vec<(f64, Arc<Vec<f64>>)>>,
+}
+
+impl Recording {
+    pub fn load(path: &str) -> std::io::Result<Recording> {
+        let (rate, samples) = wav::read_wav(path)?;
+        let peak = samples.iter().fold(0.0f64, |peak, x| peak.max(x.abs()));
+        Ok(Recording {
+            path: path.to_owned(),
+            samples,
+            rate: rate as f64,
+            peak,
+            resampled: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn peak(&self) -> f64 {
+        self.peak
+    }
+
+    /// The recording resampled to `sample_rate`
+    pub fn resampled(&self, sample_rate: f64) -> Arc<Vec<f64>> {
+        let mut resampled = self.resampled.lock().unwrap();
+        if let Some((_, samples)) = resampled.iter().find(|(rate, _)| *rate == sample_rate) {
+            return samples.clone();
+        }
+        let samples = Arc::new(resample::resample(&self.samples, self.rate, sample_rate));
+        resampled.push((sample_rate, samples.clone()));
+        samples
+    }
+}
+
+impl core::fmt::Debug for Recording {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
@@ -0,0 +1,87 @@
+use super::{ChannelContext, ChannelStage};
+
+/// Delays of the parallel comb filters, in milliseconds.  These are
+/// mutually prime-ish so that their echoes don't pile up.
+const COMB_DELAYS_MSECS: [f64; 4] = [29.7, 37.1, 41.1, 43.7];
+
+/// Delays of the series all-pass filters, in milliseconds
+const ALLPASS_DELAYS_MSECS: [f64; 2] = [5.0, 1.7];
+
+const ALLPASS_GAIN: f64 = 0.7;
+
+/// A feedback delay line
+struct Delay {
+    buffer: Vec<f64>,
+    position: usize,
+}
+
+impl Delay {
+    fn new(msecs: f64, sample_rate: f64) -> Delay {
+        Delay {
+            buffer: vec![0.0; ((msecs * sample_rate / 1000.0).round() as usize).max(1)],
+            position: 0,
+        }
+    }
+
+    fn output(&self) -> f64 {
+        self.buffer[self.position]
+    }
+
+    fn push(&mut self, value: f64) {
+        self.buffer[self.position] = value;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+}
+
+/// A Schroeder reverberator, standing in for the echoes of a room
+pub struct Reverb {
+    /// Time for the reverberation to decay by 60 dB, in seconds
+    rt60: f64,
+
+    /// Proportion of the output that is reverberation rather than the
+    /// direct signal
+    mix: f64,
+}
+
+impl Reverb {
+    pub fn new(rt60: f64, mix: f64) -> Reverb {
+        Reverb { rt60, mix }
+    }
+}
+
+impl ChannelStage for Reverb {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        if self.rt60 <= 0.0 || self.mix <= 0.0 {
+            return;
+        }
+        let mut combs: Vec<(Delay, f64)> = COMB_DELAYS_MSECS
+            .iter()
+            .map(|msecs| {
+                let feedback = 10f64.powf(-3.0 * msecs / 1000.0 / self.rt60);
+                (Delay::new(*msecs, ctx.sample_rate), feedback)
+            })
+            .collect();
+        let mut allpasses: Vec<Delay> = ALLPASS_DELAYS_MSECS
+            .iter()
+            .map(|msecs| Delay::new(*msecs, ctx.sample_rate))
+            .collect();
+
+        for sample in samples.iter_mut() {
+            let dry = *sample;
+            let mut wet = 0.0;
+            for (comb, feedback) in combs.iter_mut() {
+                let delayed = comb.output();
+                comb.push(dry + delayed * *feedback);
+                wet += delayed;
+            }
+            wet /= combs.len() as f64;
+            for allpass in allpasses.iter_mut() {
+                let delayed = allpass.output();
+                let input = wet + delayed * ALLPASS_GAIN;
+                allpass.push(input);
+                wet = delayed - input * ALLPASS_GAIN;
+            }
+            *sample = dry * (1.0 - self.mix) + wet * self.mix;
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use rand::prelude::*;
+use rand_distr::StandardNormal;
+
+use std::sync::Arc;
+
+use super::recording::Recording;
+use super::{ChannelContext, ChannelStage};
+use crate::dsp;
+
+/// Early reflections arrive within this many seconds of the direct sound,
+/// after which the reverberation becomes diffuse.
+const EARLY_REFLECTION_SECS: f64 = 0.05;
+
+/// Convolve `samples` with `response`, keeping the original length.  `mix`
+/// sets how much of the output is the convolved signal rather than the
+/// original.
+fn apply_response(samples: &mut [f64], response: &[f64], mix: f64) {
+    let wet = dsp::convolve(samples, response);
+    for (sample, wet) in samples.iter_mut().zip(wet) {
+        *sample = *sample * (1.0 - mix) + wet * mix;
+    }
+}
+
+/// The impulse response of a real room, loaded from a wave file
+pub struct ImpulseResponse {
+    recording: Arc<Recording>,
+    mix: f64,
+}
+
+impl ImpulseResponse {
+    /// The response is scaled so that its peak, normally the direct sound,
+    /// has unity gain.
+    pub fn new(recording: Arc<Recording>, mix: f64) -> std::io::Result<ImpulseResponse> {
+        if recording.peak() == 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: impulse response is silent", recording.path()),
+            ));
+        }
+        Ok(ImpulseResponse { recording, mix })
+    }
+}
+
+impl ChannelStage for ImpulseResponse {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let peak = self.recording.peak();
+        let response: Vec<f64> = self
+            .recording
+            .resampled(ctx.sample_rate)
+            .iter()
+            .map(|tap| tap / peak)
+            .collect();
+        apply_response(samples, &response, self.mix);
+    }
+}
+
+/// Generate the impulse response of an imaginary room.  The direct sound
+/// arrives with unity gain, followed by `reflections` distinct echoes and a
+/// tail of diffuse reverberation that decays by 60 dB over `rt60` seconds.
+/// The reverberation is scaled to give a direct-to-reverberant energy ratio
+/// of `drr_db`.
+pub fn synthetic_response<R: Rng>(
+    sample_rate: f64,
+    rt60: f64,
+    reflections: usize,
+    drr_db: f64,
+    rng: &mut R,
+) -> Vec<f64> {
+    if rt60 <= 0.0 {
+        return vec![1.0];
+    }
+    let len = (rt60 * sample_rate).ceil() as usize + 1;
+    let early_len = ((EARLY_REFLECTION_SECS * sample_rate) as usize).clamp(2, len);
+    let decay = |n: usize| 10f64.powf(-3.0 * n as f64 / (rt60 * sample_rate));
+
+    let mut reverberant = vec![0.0; len];
+    for _ in 0..reflections {
+        let n = rng.gen_range(1, early_len);
+        let sign = if rng.gen::<bool>() { 1.0 } else { -1.0 };
+        reverberant[n] += sign * rng.gen_range(0.5, 1.0) * decay(n);
+    }
+    // The diffuse tail builds up over the early reflection period
+    for (n, tap) in reverberant.iter_mut().enumerate().skip(1) {
+        let onset = (n as f64 / early_len as f64).min(1.0);
+        let noise: f64 = rng.sample(StandardNormal);
+        *tap += noise * decay(n) * onset;
+    }
+
+    let reverberant_energy = dsp::energy(&reverberant);
+    let scale = if reverberant_energy > 0.0 {
+        (1.0 / (reverberant_energy * dsp::db_to_power(drr_db))).sqrt()
+    } else {
+        0.0
+    };
+    let mut response: Vec<f64> = reverberant.iter().map(|tap| tap * scale).collect();
+    response[0] = 1.0;
+    response
+}
+
+/// Convolution with a synthetic room response, generated from a seed so
+/// that every run through the same room sounds the same
+pub struct Room {
+    rt60: f64,
+    reflections: usize,
+    drr_db: f64,
+    seed: u64,
+    mix: f64,
+}
+
+impl Room {
+    pub fn new(rt60: f64, reflections: usize, drr_db: f64, seed: u64, mix: f64) -> Room {
+        Room {
+            rt60,
+            reflections,
+            drr_db,
+            seed,
+            mix,
+        }
+    }
+}
+
+impl ChannelStage for Room {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        let response = synthetic_response(
+            ctx.sample_rate,
+            self.rt60,
+            self.reflections,
+            self.drr_db,
+            &mut StdRng::seed_from_u64(self.seed),
+        );
+        apply_response(samples, &response, self.mix);
+    }
+}
@@ -0,0 +1,150 @@
+use super::{ChannelContext, ChannelStage};
+use crate::dsp::{self, FrequencyResponse};
+
+/// Typical magnitude responses of the devices the stickers are programmed
+/// from, as pairs of frequency in Hz and gain in dB relative to 1 kHz.
+/// These are the general shapes of each class of device rather than
+/// measurements of any one model, and a measured response can be loaded
+/// from a CSV file instead.
+const PROFILES: &[(&str, &[(f64, f64)])] = &[
+    (
+        // Small phone loudspeaker: no bass, and a steep fall above 14 kHz
+        "phone-speaker",
+        &[
+            (200.0, -25.0),
+            (500.0, -8.0),
+            (1000.0, 0.0),
+            (8000.0, 0.0),
+            (12000.0, -3.0),
+            (14000.0, -6.0),
+            (16000.0, -12.0),
+            (18000.0, -20.0),
+            (20000.0, -30.0),
+            (22000.0, -40.0),
+        ],
+    ),
+    (
+        // Tablet loudspeaker: larger, with a gentler top end
+        "tablet-speaker",
+        &[
+            (100.0, -20.0),
+            (300.0, -6.0),
+            (1000.0, 0.0),
+            (10000.0, 0.0),
+            (14000.0, -2.0),
+            (17000.0, -6.0),
+            (19000.0, -12.0),
+            (21000.0, -20.0),
+            (22000.0, -26.0),
+        ],
+    ),
+    (
+        // Laptop loudspeaker: small drivers behind a grille
+        "laptop-speaker",
+        &[
+            (200.0, -18.0),
+            (600.0, -4.0),
+            (1000.0, 0.0),
+            (6000.0, 0.0),
+            (10000.0, -4.0),
+            (13000.0, -10.0),
+            (16000.0, -20.0),
+            (19000.0, -32.0),
+            (22000.0, -45.0),
+        ],
+    ),
+    (
+        // MEMS microphone: flat, then rising into the port resonance
+        "mems-mic",
+        &[
+            (100.0, -2.0),
+            (1000.0, 0.0),
+            (8000.0, 0.5),
+            (12000.0, 2.0),
+            (15000.0, 4.0),
+            (18000.0, 5.0),
+            (20000.0, 2.0),
+            (22000.0, -6.0),
+        ],
+    ),
+    (
+        // Electret microphone behind an acoustic mesh
+        "electret-mic",
+        &[
+            (100.0, -3.0),
+            (1000.0, 0.0),
+            (6000.0, 0.0),
+            (10000.0, -2.0),
+            (13000.0, -6.0),
+            (16000.0, -12.0),
+            (20000.0, -24.0),
+        ],
+    ),
+];
+
+/// Names of the built-in device profiles
+pub fn profile_names() -> Vec<&'static str> {
+    PROFILES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Look up a built-in profile by name, or load a response from a CSV file
+/// of `frequency, gain_db` pairs.
+pub fn load_response(name: &str) -> std::io::Result<FrequencyResponse> {
+    match PROFILES.iter().find(|(profile, _)| *profile == name) {
+        Some((_, points)) => Ok(FrequencyResponse::new(points.to_vec())),
+        None => FrequencyResponse::load(name),
+    }
+}
+
+/// The speaker and microphone at either end of the link, realized as a
+/// linear-phase FIR filter
+pub struct Transducer {
+    responses: Vec<FrequencyResponse>,
+    taps: usize,
+
+    /// Overall gain at F_LO and F_HI in the last run, in dB
+    gain_lo: f64,
+    gain_hi: f64,
+}
+
+impl Transducer {
+    pub fn new(responses: Vec<FrequencyResponse>, taps: usize) -> Transducer {
+        Transducer {
+            responses,
+            taps,
+            gain_lo: 0.0,
+            gain_hi: 0.0,
+        }
+    }
+
+    fn gain_db(&self, frequency: f64) -> f64 {
+        self.responses
+            .iter()
+            .map(|response| response.gain_db(frequency))
+            .sum()
+    }
+}
+
+impl ChannelStage for Transducer {
+    fn process(&mut self, samples: &mut Vec<f64>, ctx: &mut ChannelContext) {
+        self.gain_lo = self.gain_db(ctx.f_lo);
+        self.gain_hi = self.gain_db(ctx.f_hi);
+        if self.responses.is_empty() {
+            return;
+        }
+        let taps = dsp::design_fir(
+            |f| dsp::db_to_gain(self.gain_db(f)),
+            self.taps,
+            ctx.sample_rate,
+        );
+        *samples = dsp::fir_filter(samples, &taps);
+    }
+
+    fn measurement_names(&self) -> &'static [&'static str] {
+        &["gain_lo", "gain_hi"]
+    }
+
+    fn measurements(&self) -> Vec<f64> {
+        vec![self.gain_lo, self.gain_hi]
+    }
+}
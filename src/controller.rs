@@ -3,7 +3,9 @@ use crypto::digest::Digest;
 use crypto::md5::Md5;
 use std::io::Cursor;
 
-use crate::modulator;
+use crate::envelope::Envelope;
+use crate::hopping::{Hop, HopInterval, HopPlan, HopSequence};
+use crate::modulator::{self, PhaseMode};
 use crate::EncodingRate;
 
 /// Which version of the data strip pattern is used
@@ -25,13 +27,62 @@ impl ProtocolVersion {
     }
 }
 
+/// What a packet carries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacketKind {
+    /// The length, hash and GUID of the whole program
+    Control,
+
+    /// One block of the program
+    Data(u16),
+}
+
+/// A packet as it was sent, for comparing against what was received
+#[derive(Clone, Debug)]
+pub struct SentPacket {
+    pub kind: PacketKind,
+
+    /// Which time through the whole program this packet was sent in,
+    /// counting from zero
+    pub pass: usize,
+
+    /// The sample at which the first bit of the packet begins
+    pub start_sample: usize,
+
+    /// Every byte of the packet, from the preamble to the stop bytes, in the
+    /// order they were sent.  Each byte is sent least significant bit first.
+    pub bytes: Vec<u8>,
+}
+
 pub struct Controller {
     rate: f64,
+    baud_rate: f64,
     os_update: bool,
     modulator: modulator::Modulator,
     protocol_version: ProtocolVersion,
     preamble: Vec<u8>,
     stop_bytes: Vec<u8>,
+    envelope: Envelope,
+
+    /// Samples of release ramp that have been written past the end of the
+    /// last burst, and which should be taken out of the following silence.
+    pending_release: usize,
+
+    /// Where the last burst finished in the output, used to tell the
+    /// modulator how long it has been silent.
+    last_burst_end: Option<usize>,
+
+    /// When frequency hopping, how often to hop and where to hop to next
+    hopping: Option<(HopInterval, HopSequence)>,
+
+    /// Every time the transmitter retuned, and where
+    hops: Vec<Hop>,
+
+    /// Every packet sent so far
+    packets: Vec<SentPacket>,
+
+    /// The number of times the whole program has been sent so far
+    passes: usize,
 }
 
 // Preamble sent before every audio packet
@@ -57,11 +108,55 @@ impl Controller {
     ) -> Controller {
         Controller {
             rate: sample_rate,
+            baud_rate,
             os_update,
             protocol_version,
             modulator: modulator::Modulator::new(sample_rate, baud_rate, f_lo, f_hi),
             preamble: PREAMBLE.to_vec(),
             stop_bytes: STOP_BYTES.to_vec(),
+            envelope: Envelope::default(),
+            pending_release: 0,
+            last_burst_end: None,
+            hopping: None,
+            hops: vec![],
+            packets: vec![],
+            passes: 0,
+        }
+    }
+
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
+
+    pub fn set_phase_mode(&mut self, phase_mode: PhaseMode) {
+        self.modulator.set_phase_mode(phase_mode);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.modulator.set_seed(seed);
+    }
+
+    pub fn set_hop_plan(&mut self, plan: &HopPlan) {
+        self.hopping = Some((plan.interval, plan.sequence()));
+    }
+
+    /// The tone changes made so far, with positions in samples
+    pub fn hops(&self) -> &[Hop] {
+        &self.hops
+    }
+
+    /// The packets sent so far, with positions in samples
+    pub fn packets(&self) -> &[SentPacket] {
+        &self.packets
+    }
+
+    /// Move to the next tone pair in the hop sequence, if hopping.
+    fn retune(&mut self, position: usize) {
+        if let Some((_, sequence)) = self.hopping.as_mut() {
+            if let Some((f_lo, f_hi)) = sequence.next() {
+                self.modulator.set_tones(f_lo, f_hi);
+                self.hops.push(Hop::new(position, f_lo, f_hi));
+            }
         }
     }
 
@@ -224,21 +319,72 @@ impl Controller {
 
     pub fn make_silence(&mut self, msecs: u32, buffer: &mut Vec<f64>) {
         let silence_length = (self.rate / (1000.0 / msecs as f64)).ceil() as usize;
+        let silence_length = silence_length.saturating_sub(self.pending_release);
+        self.pending_release = 0;
         buffer.resize(buffer.len() + silence_length, 0f64);
     }
 
-    pub fn make_zero(&mut self, number: u32) -> Vec<u8> {
-        let mut buffer: Vec<u8> = vec![];
+    /// Modulate a single packet, ramping its amplitude up and down according
+    /// to the envelope.  The attack is borrowed from the end of any preceding
+    /// silence and the release from the start of the next one, so packets
+    /// start at the same time and the preamble is sent at full amplitude.
+    /// Returns the sample at which the first bit begins.
+    pub fn burst(&mut self, data: &[u8], output: &mut Vec<f64>) -> usize {
+        let attack = self.envelope.attack_samples(self.rate);
+        let release = self.envelope.release_samples(self.rate);
+
+        let trailing_silence = output
+            .iter()
+            .rev()
+            .take(attack)
+            .take_while(|s| **s == 0.0)
+            .count();
+        output.truncate(output.len() - trailing_silence);
+
+        let start = output.len();
+        if let Some(end) = self.last_burst_end {
+            self.modulator.skip(start.saturating_sub(end));
+        }
+        let chunk_size = match self.hopping {
+            Some((HopInterval::Bytes(n), _)) => n.max(1),
+            _ => data.len().max(1),
+        };
+        self.retune(start);
+        self.modulator.begin_burst(attack, output);
+        let data_start = output.len();
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            if i > 0 {
+                self.retune(output.len());
+            }
+            self.modulator.modulate_bytes(chunk, output);
+        }
+        self.modulator
+            .end_burst(data.last().cloned().unwrap_or(0), release, output);
+        self.envelope.apply(&mut output[start..], attack, release);
+        self.pending_release = release;
+        self.last_burst_end = Some(output.len());
+        data_start
+    }
 
-        buffer.resize((number / 8) as usize, 0);
+    /// Send a packet, keeping a record of it
+    fn send(&mut self, kind: PacketKind, data: Vec<u8>, output: &mut Vec<f64>) {
+        let start_sample = self.burst(&data, output);
+        self.packets.push(SentPacket {
+            kind,
+            pass: self.passes,
+            start_sample,
+            bytes: data,
+        });
+    }
 
-        buffer
+    pub fn make_zero(&mut self, number: u32) -> Vec<u8> {
+        vec![0; (number / 8) as usize]
     }
 
     pub fn pilot(&mut self, output: &mut Vec<f64>, rate: &EncodingRate) {
         if *rate == EncodingRate::Low {
-            let data = self.make_zero(4000); // ~0.5secs
-            self.modulator.modulate_pcm(&data, output);
+            let data = self.make_zero((self.baud_rate / 2.0) as u32); // ~0.5secs
+            self.burst(&data, output);
         } else {
             // // no preamble at high rate, this is the default
             // let data = self.make_one(3000); // ~0.5secs
@@ -250,28 +396,27 @@ impl Controller {
 
     /// Encode the data into a modulated AFSK signal based on the current modulation settings.
     /// Return the number of packets that were written.
-    pub fn encode(&mut self, input: &[u8], output: &mut Vec<f64>, rate: &EncodingRate) -> usize {
-        let silence_divisor = rate.silence_divisor();
+    pub fn encode(&mut self, input: &[u8], output: &mut Vec<f64>) -> usize {
         let file_length = input.len();
         let mut packet_count = 0;
 
         // Note: Maximum of 65536 blocks
         let blocks = ((file_length as f64 / 256.0).ceil()) as u16;
 
-        self.make_silence(250 / silence_divisor, output);
+        self.make_silence(250, output);
 
-        let data = self.make_control_packet(&input);
+        let data = self.make_control_packet(input);
         packet_count += 1;
-        self.modulator.modulate_pcm(&data, output);
+        self.send(PacketKind::Control, data, output);
 
-        self.make_silence(100 / silence_divisor, output);
+        self.make_silence(100, output);
 
         // Make two header packets
-        let data = self.make_control_packet(&input);
+        let data = self.make_control_packet(input);
         packet_count += 1;
-        self.modulator.modulate_pcm(&data, output);
+        self.send(PacketKind::Control, data, output);
 
-        self.make_silence(500 / silence_divisor, output);
+        self.make_silence(500, output);
 
         for mut packet_num in 0..blocks {
             packet_num &= 0xff;
@@ -285,14 +430,15 @@ impl Controller {
                     packet_data.push(0xff);
                 }
             }
-            let data = self.make_data_packet(&packet_data, packet_num as u16);
+            let data = self.make_data_packet(&packet_data, packet_num);
             packet_count += 1;
-            self.modulator.modulate_pcm(&data, output);
+            self.send(PacketKind::Data(packet_num), data, output);
 
-            self.make_silence(80 / silence_divisor, output);
+            self.make_silence(80, output);
         }
 
-        self.make_silence(500 / silence_divisor, output);
+        self.make_silence(500, output);
+        self.passes += 1;
         packet_count
     }
 }
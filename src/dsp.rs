@@ -0,0 +1,605 @@
+use std::f64::consts::PI;
+
+/// A second-order IIR section, using the coefficients from the RBJ
+/// "Audio EQ Cookbook".
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn from_coefficients(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    pub fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Biquad {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        Biquad::from_coefficients(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn highpass(sample_rate: f64, cutoff: f64, q: f64) -> Biquad {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        Biquad::from_coefficients(
+            (1.0 + cos_w0) / 2.0,
+            -(1.0 + cos_w0),
+            (1.0 + cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Q of each of the biquads that make up a Butterworth filter of the given
+/// order.  Odd orders are rounded up.
+fn butterworth_q(order: usize) -> Vec<f64> {
+    let order = order.max(2).div_ceil(2) * 2;
+    (1..=order / 2)
+        .map(|k| 1.0 / (2.0 * (PI * (2 * k - 1) as f64 / (2 * order) as f64).cos()))
+        .collect()
+}
+
+fn run_sections(samples: &[f64], mut sections: Vec<Biquad>) -> Vec<f64> {
+    samples
+        .iter()
+        .map(|x| sections.iter_mut().fold(*x, |acc, s| s.process(acc)))
+        .collect()
+}
+
+/// Run `samples` through a Butterworth low-pass filter of the given order.
+pub fn lowpass_order(samples: &[f64], sample_rate: f64, cutoff: f64, order: usize) -> Vec<f64> {
+    let sections = butterworth_q(order)
+        .iter()
+        .map(|q| Biquad::lowpass(sample_rate, cutoff, *q))
+        .collect();
+    run_sections(samples, sections)
+}
+
+/// Run `samples` through a Butterworth high-pass filter of the given order.
+pub fn highpass_order(samples: &[f64], sample_rate: f64, cutoff: f64, order: usize) -> Vec<f64> {
+    let sections = butterworth_q(order)
+        .iter()
+        .map(|q| Biquad::highpass(sample_rate, cutoff, *q))
+        .collect();
+    run_sections(samples, sections)
+}
+
+/// Run `samples` through a 4th-order Butterworth low-pass filter.
+pub fn lowpass(samples: &[f64], sample_rate: f64, cutoff: f64) -> Vec<f64> {
+    lowpass_order(samples, sample_rate, cutoff, 4)
+}
+
+/// Low-pass `samples` forwards and then backwards, which cancels the phase
+/// shift and spreads any ringing evenly either side of a transient.
+pub fn lowpass_zero_phase(samples: &[f64], sample_rate: f64, cutoff: f64) -> Vec<f64> {
+    let mut filtered = lowpass(samples, sample_rate, cutoff);
+    filtered.reverse();
+    let mut filtered = lowpass(&filtered, sample_rate, cutoff);
+    filtered.reverse();
+    filtered
+}
+
+pub fn energy(samples: &[f64]) -> f64 {
+    samples.iter().map(|x| x * x).sum()
+}
+
+/// Length of the blocks used to tell bursts apart from silence, in seconds
+const POWER_BLOCK_SECS: f64 = 0.01;
+
+/// Blocks more than this far below the loudest block count as silence
+const POWER_GATE_DB: f64 = -30.0;
+
+/// Mean power of the parts of `samples` where a signal is present.  The
+/// gaps between packets would otherwise drag the average down, making the
+/// signal appear weaker than it is while it's actually being received.
+pub fn active_power(samples: &[f64], sample_rate: f64) -> f64 {
+    let block_len = ((sample_rate * POWER_BLOCK_SECS) as usize).max(1);
+    let blocks: Vec<f64> = samples
+        .chunks(block_len)
+        .map(|block| energy(block) / block.len() as f64)
+        .collect();
+    let loudest = blocks.iter().fold(0.0f64, |max, p| max.max(*p));
+    let threshold = loudest * db_to_power(POWER_GATE_DB);
+    let active: Vec<f64> = blocks.into_iter().filter(|p| *p > threshold).collect();
+    if active.is_empty() {
+        return 0.0;
+    }
+    active.iter().sum::<f64>() / active.len() as f64
+}
+
+/// Measure the energy below `cutoff` that spills out of the bursts into the
+/// digital silence around them, in dB relative to the total energy of the
+/// signal.  A hard edge on a burst is a broadband click which shows up here,
+/// while the steady-state modulation inside the burst does not.
+pub fn click_energy_db(samples: &[f64], sample_rate: f64, cutoff: f64) -> f64 {
+    let total = energy(samples);
+    if total == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    let low = lowpass_zero_phase(samples, sample_rate, cutoff);
+    let splatter: f64 = samples
+        .iter()
+        .zip(low.iter())
+        .filter(|(original, _)| **original == 0.0)
+        .map(|(_, filtered)| filtered * filtered)
+        .sum();
+    10.0 * (splatter / total).log10()
+}
+
+/// Design a linear-phase FIR filter with `taps` coefficients whose magnitude
+/// response approximates `magnitude`, a function from frequency in Hz to
+/// linear gain.  The response is sampled on a dense grid, transformed back
+/// to the time domain and windowed.
+pub fn design_fir<F: Fn(f64) -> f64>(magnitude: F, taps: usize, sample_rate: f64) -> Vec<f64> {
+    let taps = taps | 1;
+    let grid = (taps * 16).max(1024);
+    let centre = (taps / 2) as f64;
+    let gains: Vec<f64> = (0..=grid / 2)
+        .map(|k| magnitude(k as f64 * sample_rate / grid as f64))
+        .collect();
+    (0..taps)
+        .map(|n| {
+            let t = n as f64 - centre;
+            let mut sum = gains[0];
+            for (k, gain) in gains.iter().enumerate().skip(1) {
+                let weight = if k == grid / 2 { 1.0 } else { 2.0 };
+                sum += weight * gain * (2.0 * PI * k as f64 * t / grid as f64).cos();
+            }
+            let window = 0.42
+                + 0.5 * (2.0 * PI * t / (taps as f64 - 1.0)).cos()
+                + 0.08 * (4.0 * PI * t / (taps as f64 - 1.0)).cos();
+            sum / grid as f64 * window
+        })
+        .collect()
+}
+
+/// Convolve `samples` with a linear-phase FIR filter, compensating for the
+/// filter's delay so that the output lines up with the input.
+pub fn fir_filter(samples: &[f64], taps: &[f64]) -> Vec<f64> {
+    let centre = taps.len() / 2;
+    let len = samples.len();
+    let mut output = vec![0.0; len];
+    for (k, tap) in taps.iter().enumerate() {
+        // output[n] += tap * samples[n + centre - k], for every n that
+        // lands inside of `samples`
+        let first = k.saturating_sub(centre);
+        let last = len.saturating_sub(centre.saturating_sub(k));
+        for n in first..last {
+            output[n] += tap * samples[n + centre - k];
+        }
+    }
+    output
+}
+
+/// Scale `samples` down, if necessary, so that the peak fits within [-1, 1].
+pub fn limit_peak(samples: &mut [f64]) {
+    let peak = samples.iter().fold(0.0f64, |peak, x| peak.max(x.abs()));
+    if peak > 1.0 {
+        for sample in samples.iter_mut() {
+            *sample /= peak;
+        }
+    }
+}
+
+/// A magnitude response measured at a number of frequencies, such as that of
+/// a speaker and microphone pair.
+#[derive(Clone, Debug)]
+pub struct FrequencyResponse {
+    /// Pairs of frequency in Hz and gain in dB, sorted by frequency
+    points: Vec<(f64, f64)>,
+}
+
+impl FrequencyResponse {
+    pub fn new(mut points: Vec<(f64, f64)>) -> FrequencyResponse {
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        FrequencyResponse { points }
+    }
+
+    /// Load a response from a CSV file with one `frequency, gain_db` pair per
+    /// line.  Blank lines, lines starting with `#` and a non-numeric header
+    /// line are ignored.  Every frequency and gain must be finite.
+    pub fn load(path: &str) -> std::io::Result<FrequencyResponse> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut points = vec![];
+        let mut first_line = true;
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let is_header = first_line;
+            first_line = false;
+            let fields: Vec<Result<f64, _>> =
+                line.split(',').map(|f| f.trim().parse::<f64>()).collect();
+            match fields.as_slice() {
+                [Ok(frequency), Ok(gain), ..] if frequency.is_finite() && gain.is_finite() => {
+                    points.push((*frequency, *gain))
+                }
+                [Ok(_), Ok(_), ..] => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: frequency and gain must be finite",
+                            path,
+                            line_number + 1
+                        ),
+                    ))
+                }
+                _ if is_header => continue,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: expected `frequency, gain_db`",
+                            path,
+                            line_number + 1
+                        ),
+                    ))
+                }
+            }
+        }
+        if points.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: no response points found", path),
+            ));
+        }
+        Ok(FrequencyResponse::new(points))
+    }
+
+    /// Gain in dB at `frequency`, interpolated linearly between the measured
+    /// points and held constant beyond either end.
+    pub fn gain_db(&self, frequency: f64) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if frequency <= first.0 {
+            return first.1;
+        }
+        if frequency >= last.0 {
+            return last.1;
+        }
+        let upper = self.points.iter().position(|p| p.0 >= frequency).unwrap();
+        let (f0, g0) = self.points[upper - 1];
+        let (f1, g1) = self.points[upper];
+        g0 + (g1 - g0) * (frequency - f0) / (f1 - f0)
+    }
+}
+
+pub fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+pub fn db_to_power(db: f64) -> f64 {
+    10f64.powf(db / 10.0)
+}
+
+pub fn power_to_db(power: f64) -> f64 {
+    10.0 * power.log10()
+}
+
+/// Add a sine wave to `samples`, such as a narrowband interferer.
+pub fn add_tone(samples: &mut [f64], sample_rate: f64, frequency: f64, amplitude: f64, phase: f64) {
+    let omega = 2.0 * PI * frequency / sample_rate;
+    for (n, sample) in samples.iter_mut().enumerate() {
+        *sample += amplitude * (omega * n as f64 + phase).sin();
+    }
+}
+
+/// A complex number, for use with `fft()`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+}
+
+impl core::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl core::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl core::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// In-place radix-2 fast Fourier transform.  The length of `buffer` must be
+/// a power of two.  The inverse transform is scaled by 1/N, so that a
+/// forward and inverse transform gives back the original signal.
+pub fn fft(buffer: &mut [Complex], inverse: bool) {
+    let len = buffer.len();
+    assert!(len.is_power_of_two(), "FFT length must be a power of two");
+
+    // Put the input into bit-reversed order
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= len {
+        let angle = sign * 2.0 * PI / size as f64;
+        let step = Complex::new(angle.cos(), angle.sin());
+        for start in (0..len).step_by(size) {
+            let mut twiddle = Complex::new(1.0, 0.0);
+            for k in 0..size / 2 {
+                let even = buffer[start + k];
+                let odd = buffer[start + k + size / 2] * twiddle;
+                buffer[start + k] = even + odd;
+                buffer[start + k + size / 2] = even - odd;
+                twiddle = twiddle * step;
+            }
+        }
+        size *= 2;
+    }
+
+    if inverse {
+        for value in buffer.iter_mut() {
+            value.re /= len as f64;
+            value.im /= len as f64;
+        }
+    }
+}
+
+/// Convolve `signal` with `kernel` using FFT overlap-add, which is much
+/// faster than direct convolution for long kernels such as the impulse
+/// response of a room.  The output is `kernel.len() - 1` samples longer
+/// than `signal`.
+pub fn convolve(signal: &[f64], kernel: &[f64]) -> Vec<f64> {
+    if signal.is_empty() || kernel.is_empty() {
+        return vec![];
+    }
+    let size = (2 * kernel.len()).next_power_of_two();
+    let block = size - kernel.len() + 1;
+
+    let mut kernel_spectrum = vec![Complex::default(); size];
+    for (bin, tap) in kernel_spectrum.iter_mut().zip(kernel.iter()) {
+        bin.re = *tap;
+    }
+    fft(&mut kernel_spectrum, false);
+
+    let mut output = vec![0.0; signal.len() + kernel.len() - 1];
+    let mut buffer = vec![Complex::default(); size];
+    for (index, chunk) in signal.chunks(block).enumerate() {
+        for (n, bin) in buffer.iter_mut().enumerate() {
+            *bin = Complex::new(chunk.get(n).copied().unwrap_or(0.0), 0.0);
+        }
+        fft(&mut buffer, false);
+        for (bin, k) in buffer.iter_mut().zip(kernel_spectrum.iter()) {
+            *bin = *bin * *k;
+        }
+        fft(&mut buffer, true);
+        let offset = index * block;
+        for (out, bin) in output[offset..]
+            .iter_mut()
+            .zip(buffer.iter().take(chunk.len() + kernel.len() - 1))
+        {
+            *out += bin.re;
+        }
+    }
+    output
+}
+
+/// The analytic signal of `samples`, whose real part is the original signal
+/// and whose imaginary part is its Hilbert transform.  Multiplying it by a
+/// complex exponential shifts every frequency by the same amount.
+pub fn analytic_signal(samples: &[f64]) -> Vec<Complex> {
+    let size = samples.len().next_power_of_two();
+    let mut spectrum: Vec<Complex> = (0..size)
+        .map(|n| Complex::new(samples.get(n).copied().unwrap_or(0.0), 0.0))
+        .collect();
+    fft(&mut spectrum, false);
+    // Keep DC and Nyquist, double the positive frequencies and remove the
+    // negative ones.
+    for (k, bin) in spectrum.iter_mut().enumerate().skip(1) {
+        if k < size / 2 {
+            bin.re *= 2.0;
+            bin.im *= 2.0;
+        } else if k > size / 2 {
+            *bin = Complex::default();
+        }
+    }
+    fft(&mut spectrum, true);
+    spectrum.truncate(samples.len());
+    spectrum
+}
+
+/// Random noise added before rounding to a lower resolution, which turns
+/// the rounding error into a steady noise floor instead of distortion that
+/// follows the signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    None,
+
+    /// Uniform across one step
+    Rectangular,
+
+    /// Sum of two uniform values, spanning two steps
+    Triangular,
+}
+
+impl Dither {
+    pub fn parse(name: &str) -> Option<Dither> {
+        match name {
+            "none" => Some(Dither::None),
+            "rpdf" => Some(Dither::Rectangular),
+            "tpdf" => Some(Dither::Triangular),
+            _ => None,
+        }
+    }
+
+    /// A dither value, in steps of the output resolution
+    pub fn sample<R: rand::Rng + ?Sized>(self, rng: &mut R) -> f64 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Rectangular => rng.gen_range(-0.5, 0.5),
+            Dither::Triangular => rng.gen_range(-0.5, 0.5) + rng.gen_range(-0.5, 0.5),
+        }
+    }
+}
+
+impl core::fmt::Display for Dither {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Dither::None => write!(f, "none"),
+            Dither::Rectangular => write!(f, "rpdf"),
+            Dither::Triangular => write!(f, "tpdf"),
+        }
+    }
+}
+
+/// Round every sample to one of the levels of a 16-bit converter after
+/// adding `dither`, as the transmitter does before anything is played.
+/// Full scale is ±32767, the same as the waveform handed to the receiver.
+pub fn quantize_16_bits<R: rand::Rng + ?Sized>(samples: &mut [f64], dither: Dither, rng: &mut R) {
+    for sample in samples.iter_mut() {
+        let code = (sample.clamp(-1.0, 1.0) * 32767.0 + dither.sample(rng)).round();
+        *sample = code.clamp(-32767.0, 32767.0) / 32767.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn direct_convolve(signal: &[f64], kernel: &[f64]) -> Vec<f64> {
+        let mut output = vec![0.0; signal.len() + kernel.len() - 1];
+        for (i, x) in signal.iter().enumerate() {
+            for (j, k) in kernel.iter().enumerate() {
+                output[i + j] += x * k;
+            }
+        }
+        output
+    }
+
+    fn random_samples(rng: &mut StdRng, len: usize) -> Vec<f64> {
+        (0..len).map(|_| rng.gen_range(-1.0, 1.0)).collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (n, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-9, "sample {}: {} != {}", n, a, e);
+        }
+    }
+
+    #[test]
+    fn convolve_matches_direct_convolution() {
+        let mut rng = StdRng::seed_from_u64(1);
+        // A single block, many blocks with a ragged end, and a kernel
+        // longer than the signal
+        for (signal_len, kernel_len) in [(10, 3), (1000, 17), (5, 64), (333, 100)] {
+            let signal = random_samples(&mut rng, signal_len);
+            let kernel = random_samples(&mut rng, kernel_len);
+            assert_close(
+                &convolve(&signal, &kernel),
+                &direct_convolve(&signal, &kernel),
+            );
+        }
+    }
+
+    #[test]
+    fn convolve_with_impulse_delays_and_scales() {
+        let signal = [1.0, 2.0, 3.0, -4.0];
+        assert_close(
+            &convolve(&signal, &[0.0, 0.0, 0.5]),
+            &[0.0, 0.0, 0.5, 1.0, 1.5, -2.0],
+        );
+        assert_close(&convolve(&signal, &[1.0]), &signal);
+    }
+
+    #[test]
+    fn convolve_empty() {
+        assert!(convolve(&[], &[1.0, 2.0]).is_empty());
+        assert!(convolve(&[1.0, 2.0], &[]).is_empty());
+    }
+
+    #[test]
+    fn quantize_lands_on_16_bit_levels() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let input = random_samples(&mut rng, 1000);
+        for dither in [Dither::None, Dither::Rectangular, Dither::Triangular] {
+            let mut samples = input.clone();
+            quantize_16_bits(&mut samples, dither, &mut rng);
+            for (x, q) in input.iter().zip(&samples) {
+                let code = q * 32767.0;
+                assert!((code - code.round()).abs() < 1e-6);
+                // Triangular dither spans two steps either way
+                assert!((q - x).abs() <= 2.5 / 32767.0);
+            }
+        }
+        let mut samples = vec![1.5, -1.5, 0.5 / 32767.0, 0.4 / 32767.0];
+        quantize_16_bits(&mut samples, Dither::None, &mut rng);
+        assert_eq!(samples, [1.0, -1.0, 1.0 / 32767.0, 0.0]);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::dsp;
+
+/// Largest boost the equalizer is allowed to apply, in dB.  A measured
+/// response that falls off a cliff would otherwise ask for unbounded gain.
+const MAX_EQUALIZER_BOOST_DB: f64 = 24.0;
+
+/// Pre-emphasis applied to the modulated waveform to make up for the speaker
+/// and microphone attenuating F_HI more than F_LO.
+#[derive(Clone, Debug)]
+pub struct PreEmphasis {
+    /// Gain of F_HI relative to F_LO, in dB.  The gain is tilted smoothly
+    /// between the two tones.
+    pub tone_gain_db: f64,
+
+    /// Measured response of the speaker and microphone, whose inverse is
+    /// applied as an equalizer
+    pub response: Option<dsp::FrequencyResponse>,
+
+    /// Length of the FIR filter
+    pub taps: usize,
+}
+
+impl PreEmphasis {
+    pub fn is_flat(&self) -> bool {
+        self.tone_gain_db == 0.0 && self.response.is_none()
+    }
+
+    /// Desired gain at `frequency`, before normalization
+    fn gain_db(&self, frequency: f64, f_lo: f64, f_hi: f64) -> f64 {
+        let mut gain = if f_hi > f_lo {
+            self.tone_gain_db * ((frequency - f_lo) / (f_hi - f_lo)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        // Flatten the measured response, keeping F_LO at unity gain
+        if let Some(response) = &self.response {
+            let relative = response.gain_db(frequency) - response.gain_db(f_lo);
+            gain -= relative.max(-MAX_EQUALIZER_BOOST_DB);
+        }
+        gain
+    }
+
+    /// Filter `samples` so that F_LO and F_HI arrive with the configured
+    /// gains, then scale the result so the peak amplitude stays within
+    /// [-1, 1].
+    pub fn apply(&self, samples: &mut Vec<f64>, sample_rate: f64, f_lo: f64, f_hi: f64) {
+        if self.is_flat() {
+            return;
+        }
+        let taps = dsp::design_fir(
+            |f| dsp::db_to_gain(self.gain_db(f, f_lo, f_hi)),
+            self.taps,
+            sample_rate,
+        );
+        *samples = dsp::fir_filter(samples, &taps);
+        dsp::limit_peak(samples);
+    }
+}
@@ -0,0 +1,86 @@
+use std::f64::consts::PI;
+
+/// The shape of the amplitude ramp applied to the start and end of a burst
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RampShape {
+    /// Start and stop at full amplitude, with no lead-in or tail
+    None,
+
+    /// Straight line from silence to full amplitude
+    Linear,
+
+    /// Half a period of a cosine, which has no discontinuity in slope
+    RaisedCosine,
+}
+
+impl RampShape {
+    /// Gain of the ramp at position `x`, where `x` runs from 0.0 (silent)
+    /// to 1.0 (full amplitude).
+    pub fn gain(self, x: f64) -> f64 {
+        match self {
+            RampShape::None => 1.0,
+            RampShape::Linear => x,
+            RampShape::RaisedCosine => 0.5 - 0.5 * (PI * x).cos(),
+        }
+    }
+}
+
+impl core::fmt::Display for RampShape {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            RampShape::None => write!(f, "none"),
+            RampShape::Linear => write!(f, "linear"),
+            RampShape::RaisedCosine => write!(f, "raised-cosine"),
+        }
+    }
+}
+
+/// Attack and release ramps applied around every packet burst.
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub shape: RampShape,
+    pub attack_msecs: f64,
+    pub release_msecs: f64,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {
+            shape: RampShape::None,
+            attack_msecs: 0.0,
+            release_msecs: 0.0,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn attack_samples(&self, sample_rate: f64) -> usize {
+        if self.shape == RampShape::None {
+            return 0;
+        }
+        (self.attack_msecs * sample_rate / 1000.0).ceil() as usize
+    }
+
+    pub fn release_samples(&self, sample_rate: f64) -> usize {
+        if self.shape == RampShape::None {
+            return 0;
+        }
+        (self.release_msecs * sample_rate / 1000.0).ceil() as usize
+    }
+
+    /// Fade in the first `attack` samples of `burst` and fade out the last
+    /// `release` samples.
+    pub fn apply(&self, burst: &mut [f64], attack: usize, release: usize) {
+        let len = burst.len();
+        for (i, sample) in burst.iter_mut().take(attack).enumerate() {
+            *sample *= self.shape.gain(i as f64 / attack as f64);
+        }
+        for (i, sample) in burst
+            .iter_mut()
+            .skip(len.saturating_sub(release))
+            .enumerate()
+        {
+            *sample *= self.shape.gain((release - i - 1) as f64 / release as f64);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+/// Tables of an experiment file whose keys are command-line options.  The
+/// split between fixed and swept parameters is only there for the reader, as
+/// any option given a range is swept.
+const OPTION_TABLES: &[&str] = &["fixed", "sweep"];
+
+/// Where the results go when the experiment doesn't say, relative to the
+/// current directory.  Each experiment gets a directory named after its file.
+const DEFAULT_RESULTS_DIRECTORY: &str = "results";
+
+pub enum ExperimentError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    BadValue(String),
+    UnknownKey(String),
+    MissingStage,
+}
+
+impl core::fmt::Debug for ExperimentError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            ExperimentError::Io(path, e) => write!(f, "unable to access {}: {:?}", path, e),
+            ExperimentError::Parse(path, e) => write!(f, "unable to parse {}: {}", path, e),
+            ExperimentError::BadValue(key) => {
+                write!(f, "{} must be a string, number or boolean", key)
+            }
+            ExperimentError::UnknownKey(key) => write!(f, "unknown key {}", key),
+            ExperimentError::MissingStage => write!(f, "channel stage is missing its `stage` key"),
+        }
+    }
+}
+
+/// An experiment file, which describes a sweep in TOML rather than on the
+/// command line.  For example:
+///
+/// ```toml
+/// input = "test/reference.bin"
+/// seed = 1
+///
+/// [output]
+/// directory = "results/snr"
+/// file = "snr.csv"
+///
+/// [fixed]
+/// encoding-rate = "mid"
+///
+/// [sweep]
+/// filter-width = "8..8..32"
+/// snr = "0..2..20"
+///
+/// [[channel]]
+/// stage = "lowpass"
+/// cutoff = "8000..2000..16000"
+/// ```
+///
+/// Keys at the top level and in `[fixed]` and `[sweep]` are the long names of
+/// command-line options, and take the same values.  A boolean turns a flag on
+/// or off, and an array is a list of values to sweep over.  Each
+/// `[[channel]]` table is a `--channel` stage, in order.
+pub struct Experiment {
+    path: PathBuf,
+
+    /// The command-line options the experiment stands for
+    pub args: Vec<String>,
+
+    /// The directory holding the results, and a copy of the experiment file
+    directory: PathBuf,
+}
+
+impl Experiment {
+    pub fn load(path: &str) -> Result<Experiment, ExperimentError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ExperimentError::Io(path.to_owned(), e))?;
+        let table = toml::from_str::<toml::value::Table>(&text)
+            .map_err(|e| ExperimentError::Parse(path.to_owned(), e))?;
+        let name = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "experiment".to_owned());
+
+        let mut args = vec![];
+        let mut directory = Path::new(DEFAULT_RESULTS_DIRECTORY).join(&name);
+        let mut file = format!("{}.csv", name);
+        for (key, value) in &table {
+            match (key.as_str(), value) {
+                ("output", Value::Table(output)) => {
+                    for (key, value) in output {
+                        match key.as_str() {
+                            "directory" => directory = PathBuf::from(option_value(key, value)?),
+                            "file" => file = option_value(key, value)?,
+                            _ => {
+                                return Err(ExperimentError::UnknownKey(format!("output.{}", key)))
+                            }
+                        }
+                    }
+                }
+                ("channel", Value::Array(stages)) => {
+                    for stage in stages {
+                        args.push(format!("--channel={}", stage_spec(stage)?));
+                    }
+                }
+                (table, Value::Table(options)) if OPTION_TABLES.contains(&table) => {
+                    for (key, value) in options {
+                        push_option(&mut args, key, value)?;
+                    }
+                }
+                (_, Value::Table(_)) => return Err(ExperimentError::UnknownKey(key.to_owned())),
+                _ => push_option(&mut args, key, value)?,
+            }
+        }
+        args.push(format!(
+            "--output={}",
+            directory.join(file).to_string_lossy()
+        ));
+
+        Ok(Experiment {
+            path: PathBuf::from(path),
+            args,
+            directory,
+        })
+    }
+
+    /// Create the results directory and copy the experiment file into it, so
+    /// the results always sit next to the description of how they were made.
+    pub fn save_copy(&self) -> Result<(), ExperimentError> {
+        let directory_name = self.directory.to_string_lossy().into_owned();
+        std::fs::create_dir_all(&self.directory)
+            .map_err(|e| ExperimentError::Io(directory_name.clone(), e))?;
+        let copy = self
+            .directory
+            .join(self.path.file_name().unwrap_or_default());
+        let same_file = match (self.path.canonicalize(), copy.canonicalize()) {
+            (Ok(original), Ok(copy)) => original == copy,
+            _ => false,
+        };
+        if !same_file {
+            std::fs::copy(&self.path, &copy)
+                .map_err(|e| ExperimentError::Io(copy.to_string_lossy().into_owned(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a value as it would be given on the command line
+fn option_value(key: &str, value: &Value) -> Result<String, ExperimentError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(ExperimentError::BadValue(key.to_owned())),
+    }
+}
+
+fn push_option(args: &mut Vec<String>, key: &str, value: &Value) -> Result<(), ExperimentError> {
+    match value {
+        Value::Boolean(true) => args.push(format!("--{}", key)),
+        Value::Boolean(false) => (),
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(|value| option_value(key, value))
+                .collect::<Result<Vec<String>, ExperimentError>>()?;
+            args.push(format!("--{}={}", key, values.join(",")));
+        }
+        // Joining the value on with `=` keeps negative numbers from being
+        // taken as options
+        _ => args.push(format!("--{}={}", key, option_value(key, value)?)),
+    }
+    Ok(())
+}
+
+/// Turn a `[[channel]]` table into a stage of the form `KIND:PARAM=VALUE,...`
+fn stage_spec(stage: &Value) -> Result<String, ExperimentError> {
+    let table = stage
+        .as_table()
+        .ok_or_else(|| ExperimentError::BadValue("channel".to_owned()))?;
+    let kind = table
+        .get("stage")
+        .and_then(Value::as_str)
+        .ok_or(ExperimentError::MissingStage)?;
+    let params = table
+        .iter()
+        .filter(|(key, _)| key.as_str() != "stage")
+        .map(|(key, value)| Ok(format!("{}={}", key, option_value(key, value)?)))
+        .collect::<Result<Vec<String>, ExperimentError>>()?;
+    Ok(format!("{}:{}", kind, params.join(",")))
+}
@@ -1,4 +1,3 @@
-use std;
 use std::f64;
 
 pub struct FskEncoder {
@@ -18,7 +17,6 @@ pub struct FskEncoder {
 }
 
 impl FskEncoder {
-
     pub fn new(f_lo: f64, f_hi: f64, baud_rate: f64, sample_rate: f64) -> FskEncoder {
         FskEncoder {
             sample_rate,
@@ -48,7 +46,7 @@ impl FskEncoder {
         self.current_byte = 0;
         self.baud_frac = 0.0;
         */
-        output.reserve(8 * input.len() as usize * self.sample_rate as usize / self.baud_rate as usize);
+        output.reserve(8 * input.len() * self.sample_rate as usize / self.baud_rate as usize);
 
         loop {
             self.baud_frac += self.baud_incr;
@@ -61,6 +59,9 @@ impl FskEncoder {
                         self.data_pos += 1;
                         self.bit_pos = 8;
                     } else {
+                        // Undo this sample's increment so the next call
+                        // picks up the bit timing exactly where we stopped.
+                        self.baud_frac += 1.0 - self.baud_incr;
                         break;
                     }
                 }
@@ -77,6 +78,55 @@ impl FskEncoder {
         }
     }
 
+    /// Emit `count` samples of a steady tone for `bit`, keeping the phase
+    /// continuous.  Used to pad the start and end of a burst.  The bit being
+    /// sent and the bit clock are left where they were; see `advance_clock`.
+    pub fn hold(&mut self, bit: u8, count: usize, output: &mut Vec<f64>) {
+        for _ in 0..count {
+            output.push(self.phase.sin());
+            if bit == 0 {
+                self.phase += self.omega_lo;
+            } else {
+                self.phase += self.omega_hi;
+            }
+        }
+    }
+
+    /// Let `count` samples pass without any output, as if the carrier and
+    /// bit clock had kept running through a period of silence.
+    pub fn advance(&mut self, count: usize) {
+        let omega = if self.current_bit == 0 {
+            self.omega_lo
+        } else {
+            self.omega_hi
+        };
+        self.phase = (self.phase + omega * count as f64) % (2.0 * std::f64::consts::PI);
+        self.advance_clock(count);
+    }
+
+    /// Run the bit clock on by `count` samples without touching the phase.
+    pub fn advance_clock(&mut self, count: usize) {
+        self.baud_frac = (self.baud_frac + self.baud_incr * count as f64).fract();
+    }
+
+    /// Give up the bit timing kept seamless by `modulate`, counting the
+    /// sample that found the data exhausted against the bit clock as the
+    /// encoder always used to.  The next call then opens with part of a bit
+    /// of whichever tone this one finished on.
+    pub fn drop_sample(&mut self) {
+        self.baud_frac += self.baud_incr - 1.0;
+    }
+
+    /// Retune to a new pair of tones, keeping the phase continuous.
+    pub fn set_tones(&mut self, f_lo: f64, f_hi: f64) {
+        self.omega_lo = (2.0 * std::f64::consts::PI * f_lo) / self.sample_rate;
+        self.omega_hi = (2.0 * std::f64::consts::PI * f_hi) / self.sample_rate;
+    }
+
+    pub fn set_phase(&mut self, phase: f64) {
+        self.phase = phase;
+    }
+
     pub fn reset(&mut self) {
         self.phase = 0.0;
     }
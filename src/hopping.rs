@@ -0,0 +1,114 @@
+use rand::prelude::*;
+
+/// How often the transmitter moves to a new tone pair
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HopInterval {
+    /// Change tones at the start of every packet
+    Packet,
+
+    /// Change tones every N bytes, including partway through a packet
+    Bytes(usize),
+}
+
+impl core::fmt::Display for HopInterval {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            HopInterval::Packet => write!(f, "packet"),
+            HopInterval::Bytes(n) => write!(f, "{} bytes", n),
+        }
+    }
+}
+
+/// A set of tone pairs and a seed, which together describe a hop sequence
+/// known to both ends of the link.
+#[derive(Clone, Debug)]
+pub struct HopPlan {
+    pub tones: Vec<(f64, f64)>,
+    pub interval: HopInterval,
+    pub seed: u64,
+}
+
+impl HopPlan {
+    /// Parse a list of tone pairs in the form `F_LO:F_HI,F_LO:F_HI,...`
+    pub fn parse_tones(input: &str) -> Result<Vec<(f64, f64)>, std::num::ParseFloatError> {
+        input
+            .split(',')
+            .map(|pair| {
+                let mut tones = pair.splitn(2, ':');
+                let f_lo = tones.next().unwrap_or("").trim().parse::<f64>()?;
+                let f_hi = tones.next().unwrap_or("").trim().parse::<f64>()?;
+                Ok((f_lo, f_hi))
+            })
+            .collect()
+    }
+
+    /// Divide every tone by `divisor`, as is done for the lower encoding rates.
+    pub fn scaled(&self, divisor: f64) -> HopPlan {
+        HopPlan {
+            tones: self
+                .tones
+                .iter()
+                .map(|(f_lo, f_hi)| (f_lo / divisor, f_hi / divisor))
+                .collect(),
+            interval: self.interval,
+            seed: self.seed,
+        }
+    }
+
+    pub fn sequence(&self) -> HopSequence {
+        HopSequence {
+            tones: self.tones.clone(),
+            rng: StdRng::seed_from_u64(self.seed),
+            last: None,
+        }
+    }
+}
+
+/// An endless pseudo-random sequence of tone pairs.  The same pair is never
+/// used twice in a row, so that each hop actually moves.
+pub struct HopSequence {
+    tones: Vec<(f64, f64)>,
+    rng: StdRng,
+    last: Option<usize>,
+}
+
+impl Iterator for HopSequence {
+    type Item = (f64, f64);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.tones.is_empty() {
+            return None;
+        }
+        let mut index = self.rng.gen_range(0, self.tones.len());
+        if self.tones.len() > 1 && Some(index) == self.last {
+            index = (index + 1 + self.rng.gen_range(0, self.tones.len() - 1)) % self.tones.len();
+        }
+        self.last = Some(index);
+        Some(self.tones[index])
+    }
+}
+
+/// The point in a transmission where the transmitter retuned.  This is
+/// handed to the receiver so it can follow the sequence.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Hop {
+    pub start_sample: u32,
+    pub f_lo: u32,
+    pub f_hi: u32,
+}
+
+impl Hop {
+    pub fn new(start_sample: usize, f_lo: f64, f_hi: f64) -> Hop {
+        Hop {
+            start_sample: start_sample as u32,
+            f_lo: receiver_value(f_lo),
+            f_hi: receiver_value(f_hi),
+        }
+    }
+}
+
+/// The demodulator only takes whole numbers of hertz and baud, so every
+/// tone and rate it's configured with is rounded to the nearest here.
+pub fn receiver_value(value: f64) -> u32 {
+    value.round() as u32
+}
@@ -1,24 +1,76 @@
+mod ber;
+mod channel;
 mod controller;
+mod dsp;
+mod emphasis;
+mod envelope;
+mod experiment;
 mod fsk;
+mod hopping;
 mod modulator;
+mod optimize;
+mod resample;
+mod stats;
 mod steppedrange;
+mod sweep;
 mod wav;
 
-use clap::{App, Arg};
-
-use itertools::iproduct;
+use clap::{App, AppSettings, Arg, SubCommand};
 
 use rand::prelude::*;
-use rand_distr::StandardNormal;
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 
-use steppedrange::{SteppedRange, SteppedRangeError};
+use ber::{BitErrors, Event, PacketErrors, Reception};
+use channel::{Channel, ChannelContext, ChannelError, StageSpec, TimeMap};
+use controller::{PacketKind, SentPacket};
+use envelope::{Envelope, RampShape};
+use experiment::{Experiment, ExperimentError};
+use hopping::{Hop, HopInterval, HopPlan};
+use modulator::PhaseMode;
+use optimize::{Objective, OptimizeError};
+use steppedrange::{SteppedRangeError, Unit};
+use sweep::{Dimension, Kind, Point, Sweep};
 
 // const DEFAULT_SAMPLE_RATE: f64 = 48000.0;
 const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
 
+/// Every option that can be swept, along with its CSV column.  Each may be
+/// given a list of values separated by commas, and numeric values may be
+/// ranges.  The channel is swept as well, as if it were one more dimension
+/// after these.
+const DIMENSIONS: &[(&str, &str, Kind)] = &[
+    ("baud-rate", "Baud Rate", Kind::Float(Unit::None)),
+    ("f-lo", "F_LO", Kind::Float(Unit::Hertz)),
+    ("f-hi", "F_HI", Kind::Float(Unit::Hertz)),
+    ("filter-width", "Filter Width", Kind::Integer(Unit::None)),
+    ("sample-rate", "Sample Rate", Kind::Float(Unit::Hertz)),
+    ("encoding-rate", "Encoding Rate", Kind::Text),
+    ("internal-rate", "Internal Rate", Kind::Float(Unit::Hertz)),
+    ("version", "Protocol Version", Kind::Text),
+    ("repeat-count", "Repeat Count", Kind::Integer(Unit::None)),
+    (
+        "silence-prefix",
+        "Silence Prefix",
+        Kind::Integer(Unit::Milliseconds),
+    ),
+    ("phase-mode", "Phase Mode", Kind::Text),
+    ("ramp", "Ramp", Kind::Text),
+    ("attack", "Attack", Kind::Float(Unit::Milliseconds)),
+    ("release", "Release", Kind::Float(Unit::Milliseconds)),
+    ("tone-gain", "Tone Gain (dB)", Kind::Float(Unit::Decibels)),
+    ("equalizer", "Equalizer", Kind::Text),
+    ("emphasis-taps", "Emphasis Taps", Kind::Integer(Unit::None)),
+    ("hop-tones", "Hop Tones", Kind::Text),
+    ("hop-interval", "Hop Interval", Kind::Text),
+    ("hop-seed", "Hop Seed", Kind::Integer(Unit::None)),
+    ("tx-dither", "TX Dither", Kind::Text),
+];
+
 #[derive(PartialEq)]
 pub enum EncodingRate {
     Low,
@@ -27,7 +79,10 @@ pub enum EncodingRate {
 }
 
 impl EncodingRate {
-    pub fn silence_divisor(&self) -> u32 {
+    /// Lower rates send at a fraction of the baud rate, with both tones
+    /// scaled down by the same amount.  They are synthesized with this much
+    /// oversampling and then decimated to the output rate.
+    pub fn divisor(&self) -> u32 {
         match *self {
             EncodingRate::Low => 4,
             EncodingRate::Mid => 2,
@@ -41,6 +96,11 @@ enum ModulationError {
     FloatParse(std::num::ParseFloatError),
     IntParse(std::num::ParseIntError),
     SteppedRangeParse(SteppedRangeError),
+    Channel(ChannelError),
+    Experiment(ExperimentError),
+    NoSuchPoint(usize, usize),
+    CantResume(String),
+    Optimize(OptimizeError),
 }
 
 impl core::fmt::Display for EncodingRate {
@@ -77,6 +137,24 @@ impl std::convert::From<SteppedRangeError> for ModulationError {
     }
 }
 
+impl std::convert::From<ChannelError> for ModulationError {
+    fn from(error: ChannelError) -> Self {
+        ModulationError::Channel(error)
+    }
+}
+
+impl std::convert::From<ExperimentError> for ModulationError {
+    fn from(error: ExperimentError) -> Self {
+        ModulationError::Experiment(error)
+    }
+}
+
+impl std::convert::From<OptimizeError> for ModulationError {
+    fn from(error: OptimizeError) -> Self {
+        ModulationError::Optimize(error)
+    }
+}
+
 impl core::fmt::Debug for ModulationError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match &self {
@@ -84,6 +162,15 @@ impl core::fmt::Debug for ModulationError {
             ModulationError::FloatParse(e) => write!(f, "Unable to parse float: {:?}", e),
             ModulationError::IntParse(e) => write!(f, "Unable to parse integer: {:?}", e),
             ModulationError::SteppedRangeParse(e) => write!(f, "Unable to parse range: {:?}", e),
+            ModulationError::Channel(e) => write!(f, "Channel error: {:?}", e),
+            ModulationError::Experiment(e) => write!(f, "Experiment error: {:?}", e),
+            ModulationError::NoSuchPoint(point, count) => write!(
+                f,
+                "No point {} to replay, as there are only {} combinations",
+                point, count
+            ),
+            ModulationError::CantResume(reason) => write!(f, "Unable to resume: {}", reason),
+            ModulationError::Optimize(e) => write!(f, "Unable to optimize: {:?}", e),
         }
     }
 }
@@ -95,9 +182,168 @@ struct ModulationConfig {
     silence_prefix: Option<u32>,
     repeat_count: u32,
     sample_rate: f64,
+    internal_rate: Option<f64>,
     baud_rate: f64,
     f_lo: f64,
     f_hi: f64,
+    envelope: Envelope,
+    pre_emphasis: emphasis::PreEmphasis,
+    phase_mode: PhaseMode,
+
+    /// Additional tone pairs to hop between, along with F_LO and F_HI
+    hopping: Option<HopPlan>,
+
+    /// Seed for anything random in the transmitter, such as the random phase
+    /// mode
+    seed: u64,
+}
+
+impl ModulationConfig {
+    /// The rate at which the waveform is synthesized before being resampled
+    /// to `sample_rate`.
+    fn modulation_rate(&self) -> f64 {
+        self.internal_rate
+            .unwrap_or(self.sample_rate * self.data_rate.divisor() as f64)
+    }
+
+    /// The baud rate, F_LO and F_HI actually sent for this encoding rate.
+    fn tone_plan(&self) -> (f64, f64, f64) {
+        let divisor = self.data_rate.divisor() as f64;
+        (
+            self.baud_rate / divisor,
+            self.f_lo / divisor,
+            self.f_hi / divisor,
+        )
+    }
+
+    /// How the receiver is configured to hear this tone plan.  Its tones
+    /// and baud rate are rounded to whole numbers.  `filter_width` is given
+    /// for the high encoding rate, and each bit of a slower rate lasts that
+    /// many times longer, so the filter is widened to match.
+    fn receiver_config(&self, filter_width: u32) -> ModulationConfigC {
+        let (baud_rate, f_lo, f_hi) = self.tone_plan();
+        ModulationConfigC {
+            sample_rate: hopping::receiver_value(self.sample_rate),
+            f_lo: hopping::receiver_value(f_lo),
+            f_hi: hopping::receiver_value(f_hi),
+            filter_width: filter_width * self.data_rate.divisor(),
+            baud_rate: hopping::receiver_value(baud_rate),
+        }
+    }
+
+    /// The highest frequency the transmission occupies, allowing for the
+    /// sidebands of F_HI
+    fn occupied_bandwidth(&self) -> f64 {
+        let (baud_rate, _, f_hi) = self.tone_plan();
+        f_hi + baud_rate / 2.0
+    }
+
+    /// The complete hop plan, including F_LO and F_HI, scaled for this
+    /// encoding rate.
+    fn hop_plan(&self) -> Option<HopPlan> {
+        self.hopping.as_ref().map(|plan| {
+            let mut tones = vec![(self.f_lo, self.f_hi)];
+            tones.extend_from_slice(&plan.tones);
+            HopPlan {
+                tones,
+                interval: plan.interval,
+                seed: plan.seed,
+            }
+            .scaled(self.data_rate.divisor() as f64)
+        })
+    }
+}
+
+/// Build the configuration for one point of a sweep.  Options with a fixed
+/// set of values have already been checked by the argument parser, and every
+/// equalizer in the sweep has already been loaded into `equalizers`.
+fn modulation_config(
+    point: &Point,
+    os_update: bool,
+    equalizers: &HashMap<String, dsp::FrequencyResponse>,
+) -> Result<ModulationConfig, ModulationError> {
+    let hopping = match point.optional("hop-tones") {
+        Some(tones) => Some(HopPlan {
+            tones: HopPlan::parse_tones(tones)?,
+            interval: match point.value("hop-interval") {
+                "packet" => HopInterval::Packet,
+                bytes => HopInterval::Bytes(bytes.parse::<usize>()?),
+            },
+            seed: point.parse::<u64>("hop-seed")?,
+        }),
+        None => None,
+    };
+    Ok(ModulationConfig {
+        data_rate: match point.value("encoding-rate") {
+            "low" => EncodingRate::Low,
+            "mid" => EncodingRate::Mid,
+            _ => EncodingRate::High,
+        },
+        os_update,
+        version: match point.value("version") {
+            "1" => controller::ProtocolVersion::V1,
+            _ => controller::ProtocolVersion::V2,
+        },
+        silence_prefix: point
+            .optional("silence-prefix")
+            .map(|s| s.parse::<u32>())
+            .transpose()?,
+        repeat_count: point.parse::<u32>("repeat-count")?,
+        sample_rate: point.parse::<f64>("sample-rate")?,
+        internal_rate: point
+            .optional("internal-rate")
+            .map(|s| s.parse::<f64>())
+            .transpose()?,
+        baud_rate: point.parse::<f64>("baud-rate")?,
+        f_lo: point.parse::<f64>("f-lo")?,
+        f_hi: point.parse::<f64>("f-hi")?,
+        envelope: Envelope {
+            shape: match point.value("ramp") {
+                "linear" => RampShape::Linear,
+                "raised-cosine" => RampShape::RaisedCosine,
+                _ => RampShape::None,
+            },
+            attack_msecs: point.parse::<f64>("attack")?,
+            release_msecs: point.parse::<f64>("release")?,
+        },
+        pre_emphasis: emphasis::PreEmphasis {
+            tone_gain_db: point.parse::<f64>("tone-gain")?,
+            response: point
+                .optional("equalizer")
+                .map(|path| equalizers[path].clone()),
+            taps: point.parse::<usize>("emphasis-taps")?,
+        },
+        phase_mode: match point.value("phase-mode") {
+            "continuous" => PhaseMode::Continuous,
+            "random" => PhaseMode::Random,
+            _ => PhaseMode::Reset,
+        },
+        hopping,
+        seed: 0,
+    })
+}
+
+/// Derive the seed for one point of a sweep from the seed of the whole
+/// sweep.  Each point gets its own seed so that it can be replayed without
+/// running any of the others.  This is the SplitMix64 finalizer, which
+/// scatters neighbouring points across the whole range.
+fn derive_seed(seed: u64, point: usize) -> u64 {
+    let mut z = seed.wrapping_add((point as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The result of modulating a file
+struct Transmission {
+    audio: Vec<f64>,
+    packet_count: usize,
+
+    /// Where the transmitter changed tones, in samples at the output rate
+    hops: Vec<Hop>,
+
+    /// Every packet sent, with positions in samples at the output rate
+    packets: Vec<SentPacket>,
 }
 
 #[repr(C)]
@@ -109,32 +355,87 @@ struct ModulationConfigC {
     baud_rate: u32,
 }
 
+/// Where the demodulator records every bit it decides on and everything
+/// its MAC does.  The counts include anything that didn't fit.
+#[repr(C)]
+struct DemodTraceC {
+    bits: *mut u8,
+    bit_samples: *mut u32,
+    max_bits: u32,
+    nbits: u32,
+    events: *mut Event,
+    max_events: u32,
+    nevents: u32,
+}
+
 extern "C" {
-    fn attempt_demodulation(
+    fn attempt_demodulation_traced(
         cfg: *const ModulationConfigC,
+        hops: *const Hop,
+        nhops: u32,
         samples: *const i16,
         nsamples: u32,
+        trace: *mut DemodTraceC,
     ) -> u32;
 }
 
+/// Demodulate `samples`, following the transmitter through `hops`.  Returns
+/// the number of packets that passed their hash check, along with what the
+/// receiver heard along the way.
+fn demodulate(ccfg: &ModulationConfigC, hops: &[Hop], samples: &[i16]) -> (usize, Reception) {
+    // Every bit takes at least one sample, and the MAC needs at least a
+    // byte's worth of bits between events
+    let mut reception = Reception {
+        bits: vec![0; samples.len()],
+        bit_samples: vec![0; samples.len()],
+        events: vec![Event::default(); samples.len() / 8 + 1],
+    };
+    let mut trace = DemodTraceC {
+        bits: reception.bits.as_mut_ptr(),
+        bit_samples: reception.bit_samples.as_mut_ptr(),
+        max_bits: reception.bits.len() as u32,
+        nbits: 0,
+        events: reception.events.as_mut_ptr(),
+        max_events: reception.events.len() as u32,
+        nevents: 0,
+    };
+    let successes = unsafe {
+        attempt_demodulation_traced(
+            ccfg,
+            hops.as_ptr(),
+            hops.len() as u32,
+            samples.as_ptr(),
+            samples.len() as u32,
+            &mut trace,
+        )
+    };
+    reception.bits.truncate(trace.nbits as usize);
+    reception.bit_samples.truncate(trace.nbits as usize);
+    reception.events.truncate(trace.nevents as usize);
+    (successes as usize, reception)
+}
+
 fn do_modulation(
     source_filename: &str,
     cfg: &ModulationConfig,
-) -> Result<(Vec<f64>, usize), std::io::Error> {
+) -> Result<Transmission, std::io::Error> {
     let mut packet_count = 0;
-    let sample_rate = match cfg.data_rate {
-        EncodingRate::Low => cfg.sample_rate * 4.0,
-        EncodingRate::Mid => cfg.sample_rate * 2.0,
-        EncodingRate::High => cfg.sample_rate * 1.0,
-    };
+    let modulation_rate = cfg.modulation_rate();
+    let (baud_rate, f_lo, f_hi) = cfg.tone_plan();
     let mut controller = controller::Controller::new(
-        sample_rate,
+        modulation_rate,
         cfg.os_update,
         cfg.version,
-        cfg.baud_rate,
-        cfg.f_lo,
-        cfg.f_hi,
+        baud_rate,
+        f_lo,
+        f_hi,
     );
+    controller.set_envelope(cfg.envelope);
+    controller.set_phase_mode(cfg.phase_mode);
+    controller.set_seed(cfg.seed);
+    if let Some(plan) = cfg.hop_plan() {
+        controller.set_hop_plan(&plan);
+    }
 
     let input_data = {
         let mut input = File::open(source_filename)?;
@@ -150,18 +451,69 @@ fn do_modulation(
     }
 
     for _ in 0..cfg.repeat_count {
-        packet_count += controller.encode(&input_data, &mut audio_data, &cfg.data_rate);
-        let mut pilot_controller = controller::Controller::new(
-            cfg.sample_rate,
-            cfg.os_update,
-            cfg.version,
-            cfg.baud_rate,
-            cfg.f_lo,
-            cfg.f_hi,
-        );
-        pilot_controller.pilot(&mut audio_data, &cfg.data_rate);
+        packet_count += controller.encode(&input_data, &mut audio_data);
+        controller.pilot(&mut audio_data, &cfg.data_rate);
+    }
+
+    cfg.pre_emphasis
+        .apply(&mut audio_data, modulation_rate, f_lo, f_hi);
+
+    let mut hops = controller.hops().to_vec();
+    let mut packets = controller.packets().to_vec();
+    if modulation_rate != cfg.sample_rate {
+        audio_data = resample::resample(&audio_data, modulation_rate, cfg.sample_rate);
+        for hop in hops.iter_mut() {
+            hop.start_sample =
+                (hop.start_sample as f64 * cfg.sample_rate / modulation_rate).round() as u32;
+        }
+        for packet in packets.iter_mut() {
+            packet.start_sample =
+                (packet.start_sample as f64 * cfg.sample_rate / modulation_rate).round() as usize;
+        }
+    }
+    Ok(Transmission {
+        audio: audio_data,
+        packet_count,
+        hops,
+        packets,
+    })
+}
+
+/// Explain what to change when the channel can't carry the tones, such as
+/// a phone call that cuts off everything above 4 kHz.  A slower encoding
+/// rate scales every tone down, which is suggested if one would fit.
+fn tone_plan_advice(cfg: &ModulationConfig, bandwidth: f64) -> Option<String> {
+    let needed = cfg.occupied_bandwidth();
+    if needed <= bandwidth {
+        return None;
     }
-    Ok((audio_data, packet_count))
+    let mut advice = format!(
+        "WARNING: the channel only passes up to {:.0} Hz, but this tone plan reaches {:.0} Hz.",
+        bandwidth, needed
+    );
+    let suggestion = [EncodingRate::Mid, EncodingRate::Low]
+        .iter()
+        .filter(|rate| rate.divisor() > cfg.data_rate.divisor())
+        .find(|rate| {
+            needed * cfg.data_rate.divisor() as f64 / (rate.divisor() as f64) <= bandwidth
+        });
+    match suggestion {
+        Some(rate) => {
+            let divisor = rate.divisor() as f64;
+            advice.push_str(&format!(
+                "  Try --encoding-rate {}, which sends F_LO {:.0} Hz and F_HI {:.0} Hz at {:.0} baud.",
+                rate.to_string().to_lowercase(),
+                cfg.f_lo / divisor,
+                cfg.f_hi / divisor,
+                cfg.baud_rate / divisor
+            ));
+        }
+        None => advice.push_str(&format!(
+            "  Choose F_HI and baud so that F_HI + baud / 2 stays below {:.0} Hz.",
+            bandwidth
+        )),
+    }
+    Some(advice)
 }
 
 fn do_play_file(audio_data: Vec<f64>, sample_rate: f64) -> ! {
@@ -191,7 +543,7 @@ fn do_play_file(audio_data: Vec<f64>, sample_rate: f64) -> ! {
                 use std::process;
                 process::exit(0);
             }
-            0.0 as f32
+            0.0f32
         } else {
             let val = audio_data[audio_data_pos];
             audio_data_pos += 1;
@@ -203,7 +555,7 @@ fn do_play_file(audio_data: Vec<f64>, sample_rate: f64) -> ! {
         match buffer {
             cpal::UnknownTypeBuffer::U16(mut buffer) => {
                 for sample in buffer.chunks_mut(format.channels.len()) {
-                    let value = ((next_value() * 0.5 + 0.5) * std::u16::MAX as f32) as u16;
+                    let value = ((next_value() * 0.5 + 0.5) * u16::MAX as f32) as u16;
                     for out in sample.iter_mut() {
                         *out = value;
                     }
@@ -212,7 +564,7 @@ fn do_play_file(audio_data: Vec<f64>, sample_rate: f64) -> ! {
 
             cpal::UnknownTypeBuffer::I16(mut buffer) => {
                 for sample in buffer.chunks_mut(format.channels.len()) {
-                    let value = (next_value() * std::i16::MAX as f32) as i16;
+                    let value = (next_value() * i16::MAX as f32) as i16;
                     for out in sample.iter_mut() {
                         *out = value;
                     }
@@ -230,11 +582,542 @@ fn do_play_file(audio_data: Vec<f64>, sample_rate: f64) -> ! {
         };
     });
 }
-fn main() -> Result<(), ModulationError> {
-    let matches = App::new("Love-to-Code Program Modulator")
+/// Find the points already in the CSV log of an interrupted sweep, so they
+/// can be skipped.  The log must have been made by the same sweep with the
+/// same seed, or the points would mean something else, so every row's
+/// parameters and seeds are checked against this sweep.  A row cut short by
+/// the interruption is removed, to be run again.
+fn completed_points(
+    path: &str,
+    header: &str,
+    sweep: &Sweep,
+    channels: &[Channel],
+    seed: u64,
+) -> Result<std::collections::HashSet<usize>, ModulationError> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let mut log = String::new();
+    file.read_to_string(&mut log)?;
+    let complete = log.rfind('\n').map(|end| end + 1).unwrap_or(0);
+    if complete < log.len() {
+        file.set_len(complete as u64)?;
+        log.truncate(complete);
+    }
+
+    let mut lines = log.lines();
+    match lines.next() {
+        Some(line) if line == header => (),
+        Some(_) => {
+            return Err(ModulationError::CantResume(format!(
+                "{} has different columns to this sweep",
+                path
+            )))
+        }
+        None => return Ok(Default::default()),
+    }
+    let columns: Vec<&str> = header.split(", ").collect();
+    let sweep_seed_column = columns.iter().position(|c| *c == "Sweep Seed").unwrap();
+    let point_column = columns.iter().position(|c| *c == "Point").unwrap();
+    let seed_column = columns.iter().position(|c| *c == "Seed").unwrap();
+    let point_count = sweep.point_count() * channels.len();
+    let mut points = std::collections::HashSet::new();
+    for (line_number, line) in lines.enumerate() {
+        // The header is line 1
+        let malformed =
+            || ModulationError::CantResume(format!("{}:{} is malformed", path, line_number + 2));
+        let fields: Vec<&str> = line.split(", ").collect();
+        if fields.len() != columns.len() {
+            return Err(malformed());
+        }
+        let point = fields[point_column]
+            .parse::<usize>()
+            .ok()
+            .filter(|point| *point < point_count)
+            .ok_or_else(malformed)?;
+        if fields[sweep_seed_column] != seed.to_string()
+            || fields[seed_column] != derive_seed(seed, point).to_string()
+        {
+            return Err(ModulationError::CantResume(format!(
+                "{} was made with a different --seed",
+                path
+            )));
+        }
+
+        // The sweep's parameters come first, and the channel's straight
+        // after the seed
+        let parameters = sweep.point(point / channels.len()).csv_values();
+        let channel = channels[point % channels.len()].values();
+        if fields[..parameters.len()] != parameters[..]
+            || fields[seed_column + 1..][..channel.len()] != channel[..]
+        {
+            return Err(ModulationError::CantResume(format!(
+                "{}:{} has different parameters to point {} of this sweep",
+                path,
+                line_number + 2,
+                point
+            )));
+        }
+        points.insert(point);
+    }
+    Ok(points)
+}
+
+/// Drop the packets of any point that isn't in the CSV log of an interrupted
+/// sweep, since those points will be run again and log their packets anew.
+/// A row cut short by the interruption is removed too.
+fn trim_packet_log(
+    path: &str,
+    completed: &std::collections::HashSet<usize>,
+) -> std::io::Result<()> {
+    let log = match std::fs::read_to_string(path) {
+        Ok(log) => log,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let complete = log.rfind('\n').map(|end| end + 1).unwrap_or(0);
+    let mut lines = log[..complete].lines();
+    let mut trimmed = String::with_capacity(complete);
+    // The header is kept, and then the packets of every completed point
+    if let Some(line) = lines.next() {
+        trimmed.push_str(line);
+        trimmed.push('\n');
+    }
+    for line in lines {
+        let point = line.split(", ").next().and_then(|point| point.parse().ok());
+        if point.is_some_and(|point| completed.contains(&point)) {
+            trimmed.push_str(line);
+            trimmed.push('\n');
+        }
+    }
+    if trimmed.len() < log.len() {
+        std::fs::write(path, trimmed)?;
+    }
+    Ok(())
+}
+
+/// Everything needed to run any point of a sweep, shared between the workers
+struct Runner<'a> {
+    source_filename: &'a str,
+    sweep: &'a Sweep,
+    channels: &'a [Channel],
+
+    /// Every equalizer used by the sweep, by path
+    equalizers: &'a HashMap<String, dsp::FrequencyResponse>,
+    seed: u64,
+    os_update: bool,
+    click_cutoff: Option<f64>,
+    play_file: bool,
+    demodulate: bool,
+
+    /// Whether to keep each trial's waveform, to be written out
+    keep_output: bool,
+
+    /// The trials to run at each point.  Each trial has its own seed.
+    trials: std::ops::Range<usize>,
+
+    /// Stop running trials at a point once the confidence interval of its
+    /// success rate is narrower than this
+    ci_width: Option<f64>,
+
+    /// Whether to describe every packet of every trial
+    packet_log: bool,
+}
+
+/// The outcome of one point of a sweep
+struct PointResult {
+    /// What happened, for the console
+    report: String,
+
+    /// The point's row of the CSV log, if it was demodulated
+    row: Option<String>,
+
+    /// The waveform of each trial as it reached the receiver, if asked for
+    outputs: Vec<Vec<i16>>,
+    sample_rate: u32,
+
+    /// The mean success rate over every trial, and the baud rate it was
+    /// achieved at, for the optimizer to score
+    success_rate: f64,
+    baud_rate: f64,
+
+    /// A row of the packet log for each packet sent, if asked for
+    packet_rows: Vec<String>,
+}
+
+/// The outcome of one trial at a point
+struct Trial {
+    click_energy: f64,
+    measurements: Vec<f64>,
+    packet_count: usize,
+    successes: usize,
+    bit_errors: BitErrors,
+    output: Vec<i16>,
+
+    /// Every packet sent, and how it fared
+    packets: Vec<(SentPacket, PacketErrors)>,
+}
+
+impl<'a> Runner<'a> {
+    fn run(&self, point: usize) -> Result<PointResult, ModulationError> {
+        let values = self.sweep.point(point / self.channels.len());
+        let channel = &self.channels[point % self.channels.len()];
+        let mut cfg = modulation_config(&values, self.os_update, self.equalizers)?;
+        let filter_width = values.parse::<u32>("filter-width")?;
+        let tx_dither = dsp::Dither::parse(values.value("tx-dither")).unwrap_or(dsp::Dither::None);
+        let point_seed = derive_seed(self.seed, point);
+
+        let mut report = String::new();
+        if let Some(advice) = channel
+            .bandwidth()
+            .and_then(|bandwidth| tone_plan_advice(&cfg, bandwidth))
+        {
+            report.push_str(&advice);
+            report.push('\n');
+        }
+        report.push_str(&format!(
+            "PARAMETERS   point: {}  seed: {}  {}channel: {}  ",
+            point,
+            point_seed,
+            values.describe(),
+            channel.describe()
+        ));
+
+        let mut trials = vec![];
+        let mut outputs = vec![];
+        let mut packet_rows = vec![];
+        for trial in self.trials.clone() {
+            // The first trial uses the point's own seed, so that a single
+            // trial is the same as not running trials at all
+            let trial_seed = match trial {
+                0 => point_seed,
+                _ => derive_seed(point_seed, trial),
+            };
+            let mut result =
+                self.run_trial(&mut cfg, channel, filter_width, tx_dither, trial_seed)?;
+            if self.keep_output {
+                outputs.push(std::mem::take(&mut result.output));
+            }
+            if self.packet_log {
+                for (index, (packet, errors)) in result.packets.iter().enumerate() {
+                    let (kind, block) = match packet.kind {
+                        PacketKind::Control => ("control", "none".to_owned()),
+                        PacketKind::Data(block) => ("data", block.to_string()),
+                    };
+                    packet_rows.push(format!(
+                        "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                        point,
+                        trial,
+                        index,
+                        kind,
+                        block,
+                        packet.pass,
+                        packet.start_sample,
+                        errors.synced as u8,
+                        errors.passed as u8,
+                        errors.bits,
+                        errors.errors,
+                        errors.slips
+                    ));
+                }
+            }
+            trials.push(result);
+
+            let successes = trials.iter().map(|t| t.successes).sum();
+            let packets = trials.iter().map(|t| t.packet_count).sum();
+            let (low, high) = stats::wilson_interval(successes, packets, stats::Z_95);
+            if self
+                .ci_width
+                .map(|width| high - low < width)
+                .unwrap_or(false)
+            {
+                break;
+            }
+        }
+
+        let mean =
+            |f: &dyn Fn(&Trial) -> f64| trials.iter().map(f).sum::<f64>() / trials.len() as f64;
+        let click_energy = mean(&|t| t.click_energy);
+        let measurements: Vec<f64> = (0..trials[0].measurements.len())
+            .map(|i| mean(&|t| t.measurements[i]))
+            .collect();
+        let packet_count: usize = trials.iter().map(|t| t.packet_count).sum();
+        let successes: usize = trials.iter().map(|t| t.successes).sum();
+        let rates: Vec<f64> = trials
+            .iter()
+            .map(|t| t.successes as f64 / t.packet_count as f64)
+            .collect();
+        let (success_rate, std_dev) = stats::mean_and_std_dev(&rates);
+        let (ci_low, ci_high) = stats::wilson_interval(successes, packet_count, stats::Z_95);
+        let mut bit_errors = BitErrors::default();
+        for trial in &trials {
+            bit_errors.add(&trial.bit_errors);
+        }
+
+        let mut row = None;
+        if self.demodulate {
+            report.push_str(&format!(
+                "CLICKS {:.1} dB  DEMOD  {:2}/{:<2} {:.3}%  BER {:.2e}  SLIPS {}  SYNC LOST {}",
+                click_energy,
+                successes,
+                packet_count,
+                success_rate * 100.0,
+                bit_errors.rate(),
+                bit_errors.slips,
+                bit_errors.sync_losses
+            ));
+            if trials.len() > 1 {
+                report.push_str(&format!(
+                    "  TRIALS {}  STD DEV {:.3}%  95% CI {:.3}%..{:.3}%",
+                    trials.len(),
+                    std_dev * 100.0,
+                    ci_low * 100.0,
+                    ci_high * 100.0
+                ));
+            }
+            report.push('\n');
+            let rx = cfg.receiver_config(filter_width);
+            row = Some(format!(
+                "{}, {}, {}, {}, {}, {}, {:.2}, {}, {}, {}{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                values.csv_values().join(", "),
+                cfg.modulation_rate(),
+                rx.baud_rate,
+                rx.f_lo,
+                rx.f_hi,
+                rx.filter_width,
+                click_energy,
+                self.seed,
+                point,
+                point_seed,
+                channel
+                    .values()
+                    .iter()
+                    .map(|value| format!(", {}", value))
+                    .chain(measurements.iter().map(|value| format!(", {:.2}", value)))
+                    .collect::<String>(),
+                packet_count,
+                successes,
+                success_rate,
+                trials.len(),
+                std_dev,
+                ci_low,
+                ci_high,
+                bit_errors.bits,
+                bit_errors.errors,
+                bit_errors.rate(),
+                bit_errors.slips,
+                bit_errors.sync_losses
+            ));
+        } else {
+            report.push_str(&format!("CLICKS {:.1} dB\n", click_energy));
+        }
+
+        Ok(PointResult {
+            report,
+            row,
+            outputs,
+            sample_rate: cfg.sample_rate as u32,
+            success_rate,
+            baud_rate: cfg.tone_plan().0,
+            packet_rows,
+        })
+    }
+
+    fn run_trial(
+        &self,
+        cfg: &mut ModulationConfig,
+        channel: &Channel,
+        filter_width: u32,
+        tx_dither: dsp::Dither,
+        seed: u64,
+    ) -> Result<Trial, ModulationError> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        cfg.seed = rng.gen();
+        let Transmission {
+            audio: mut audio_data,
+            packet_count,
+            mut hops,
+            mut packets,
+        } = do_modulation(self.source_filename, cfg)?;
+        let click_energy = dsp::click_energy_db(
+            &audio_data,
+            cfg.sample_rate,
+            self.click_cutoff.unwrap_or(cfg.tone_plan().1 / 2.0),
+        );
+
+        if self.play_file {
+            do_play_file(audio_data, cfg.sample_rate);
+        }
+        dsp::quantize_16_bits(&mut audio_data, tx_dither, &mut rng);
+        let (air_baud_rate, air_f_lo, air_f_hi) = cfg.tone_plan();
+        let mut ctx = ChannelContext {
+            sample_rate: cfg.sample_rate,
+            baud_rate: air_baud_rate,
+            f_lo: air_f_lo,
+            f_hi: air_f_hi,
+            rng: &mut rng,
+            time_map: TimeMap::default(),
+        };
+        let measurements = channel.process(&mut audio_data, &mut ctx)?;
+
+        // Follow the hops and packets to wherever the channel moved them
+        let time_map = ctx.time_map;
+        for hop in hops.iter_mut() {
+            hop.start_sample = time_map.map(hop.start_sample as f64).round() as u32;
+        }
+        for packet in packets.iter_mut() {
+            packet.start_sample = time_map.map(packet.start_sample as f64).round() as usize;
+        }
+        let mut output: Vec<i16> = Vec::new();
+        for sample in audio_data {
+            // Clamp the sample to the range (-1,1), and map it to
+            // -32767 .. 32767
+            output.push((sample.clamp(-1.0, 1.0) * 32767.0).round() as i16);
+        }
+
+        let (successes, packets) = if self.demodulate {
+            let ccfg = cfg.receiver_config(filter_width);
+            let (successes, reception) = demodulate(&ccfg, &hops, &output);
+            // The packets were sent at the transmitter's bit rate, whatever
+            // the receiver was expecting
+            let samples_per_bit = cfg.sample_rate / air_baud_rate;
+            let packet_errors = ber::measure(&packets, &reception, samples_per_bit);
+            (successes, packets.into_iter().zip(packet_errors).collect())
+        } else {
+            (0, vec![])
+        };
+        let bit_errors = packets.iter().map(|(_, errors)| errors).collect();
+
+        Ok(Trial {
+            click_energy,
+            measurements,
+            packet_count,
+            successes,
+            bit_errors,
+            output,
+            packets,
+        })
+    }
+}
+
+/// Run `points` on a pool of `jobs` worker threads, handing each result to
+/// `handle` along with its index into `points` as soon as it's finished.
+/// Stops at the first error, from either a point or `handle`.
+fn run_points(
+    runner: &Runner,
+    points: &[usize],
+    jobs: usize,
+    mut handle: impl FnMut(usize, PointResult) -> Result<(), ModulationError>,
+) -> Result<(), ModulationError> {
+    let next_point = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(points.len()) {
+            let sender = sender.clone();
+            let next_point = &next_point;
+            scope.spawn(move || loop {
+                let idx = next_point.fetch_add(1, Ordering::Relaxed);
+                if idx >= points.len() {
+                    break;
+                }
+                // The receiver goes away if another point failed
+                if sender.send((idx, runner.run(points[idx]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let receiver = receiver;
+        for (idx, result) in receiver.iter() {
+            handle(idx, result?)?;
+        }
+        Ok(())
+    })
+}
+
+/// Search the sweep for the point that does best by `objective`, rather
+/// than running every point.  Each point tried is scored on every channel
+/// and logged to `output_file` in the order it was tried, so the path the
+/// search took can be plotted.  The search gives up after `max_evaluations`
+/// points, if given, and reports the best found so far.
+fn optimize_sweep(
+    runner: &Runner,
+    objective: Objective,
+    max_evaluations: Option<usize>,
+    jobs: usize,
+    output_file: &mut File,
+) -> Result<(), ModulationError> {
+    let sweep = runner.sweep;
+    let channels = runner.channels.len();
+    writeln!(
+        output_file,
+        "Evaluation, {}, Objective, Best Objective",
+        sweep.columns().join(", ")
+    )?;
+    println!(
+        "Searching {} combinations for the best {:?} with {} jobs",
+        sweep.point_count(),
+        objective,
+        jobs
+    );
+
+    let mut evaluations = 0;
+    let mut best = f64::NEG_INFINITY;
+    let (coordinates, score) =
+        optimize::compass_search(&sweep.sizes(), max_evaluations, |candidates| {
+            let sweep_points: Vec<usize> = candidates.iter().map(|c| sweep.index(c)).collect();
+            let points: Vec<usize> = sweep_points
+                .iter()
+                .flat_map(|point| (0..channels).map(move |channel| point * channels + channel))
+                .collect();
+            let mut results = vec![(0.0, 0.0); points.len()];
+            run_points(runner, &points, jobs, |idx, result| {
+                print!("{}", result.report);
+                results[idx] = (result.success_rate, result.baud_rate);
+                Ok(())
+            })?;
+
+            let mut scores = vec![];
+            for (point, results) in sweep_points.iter().zip(results.chunks(channels)) {
+                let score = objective.score(results);
+                evaluations += 1;
+                best = best.max(score);
+                writeln!(
+                    output_file,
+                    "{}, {}, {}, {}",
+                    evaluations,
+                    sweep.point(*point).csv_values().join(", "),
+                    score,
+                    best
+                )?;
+                println!(
+                    "EVALUATION {}  objective: {}  best: {}",
+                    evaluations, score, best
+                );
+                scores.push(score);
+            }
+            Ok::<_, ModulationError>(scores)
+        })?;
+
+    println!(
+        "BEST  {}objective: {}  after {} of {} combinations",
+        sweep.point(sweep.index(&coordinates)).describe(),
+        score,
+        evaluations,
+        sweep.point_count()
+    );
+    Ok(())
+}
+
+fn app(channel_help: &str) -> App<'_, '_> {
+    App::new("Love-to-Code Program Modulator")
         .version("1.3")
         .author("Sean Cross <sean@xobs.io>")
         .about("Takes compiled code and modulates it for a Love-to-Code sticker")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .after_help("Options that describe the transmission may be given a list of values separated by commas.  Numeric values may be ranges of the form START..END or START..STEP..END, or log:START..END:COUNT for COUNT values spaced evenly on a log scale.  They may also have a metric prefix and a unit, such as 19k, 19kHz or 20ms.  Every combination of values is tried, in a random order.")
         .arg(
             Arg::with_name("input")
                 .short("i")
@@ -256,8 +1139,17 @@ fn main() -> Result<(), ModulationError> {
                 .short("r")
                 .long("rate")
                 .value_name("SAMPLE_RATE")
+                .use_delimiter(true)
                 .help("Sample rate of the output file"),
         )
+        .arg(
+            Arg::with_name("internal-rate")
+                .long("internal-rate")
+                .value_name("SAMPLE_RATE")
+                .use_delimiter(true)
+                .takes_value(true)
+                .help("Sample rate to synthesize at before resampling to the output rate (default: output rate times the encoding rate's oversampling)"),
+        )
         .arg(
             Arg::with_name("play")
                 .short("w")
@@ -269,6 +1161,7 @@ fn main() -> Result<(), ModulationError> {
                 .short("p")
                 .long("protocol-version")
                 .value_name("VERSION")
+                .use_delimiter(true)
                 .takes_value(true)
                 .possible_values(&["1", "2"])
                 .default_value("2")
@@ -279,6 +1172,7 @@ fn main() -> Result<(), ModulationError> {
                 .short("c")
                 .long("repeat-count")
                 .value_name("COUNT")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("3")
                 .help("Number of times to repeat"),
@@ -296,6 +1190,7 @@ fn main() -> Result<(), ModulationError> {
                 .long("encoding-rate")
                 .possible_values(&["high", "mid", "low"])
                 .value_name("RATE")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("high")
                 .help("Audio encoding rate"),
@@ -305,6 +1200,7 @@ fn main() -> Result<(), ModulationError> {
                 .short("b")
                 .long("baud")
                 .value_name("BAUD_RATE")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("8000")
                 .help("Baud rate for transmission"),
@@ -315,6 +1211,7 @@ fn main() -> Result<(), ModulationError> {
                 .long("f-lo")
                 .aliases(&["flo", "f_lo", "f_low", "f-low", "flow", "f_space"])
                 .value_name("F_LO")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("8666")
                 .help("Lower frequency used for F_LO / F_SPACE"),
@@ -325,6 +1222,7 @@ fn main() -> Result<(), ModulationError> {
                 .long("f-hi")
                 .aliases(&["fhi", "f_hi", "f_high", "f-high", "f_mark"])
                 .value_name("F_HI")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("12500")
                 .help("Lower frequency used for F_HI / F_MARK"),
@@ -333,43 +1231,403 @@ fn main() -> Result<(), ModulationError> {
             Arg::with_name("filter-width")
                 .long("filter-width")
                 .value_name("FILTER_WIDTH")
+                .use_delimiter(true)
                 .takes_value(true)
                 .default_value("8")
-                .help("Width of the filter to use during demodulation")
+                .help("Width of the filter to use during demodulation, in samples at the high encoding rate.  Slower rates widen it to match their longer bits")
         )
         .arg(
             Arg::with_name("silence-prefix")
                 .long("silence-prefix")
                 .value_name("MSECS")
+                .use_delimiter(true)
                 .takes_value(true)
                 .help("Number of milliseconds of silence to add to the start"),
         )
+        .arg(
+            Arg::with_name("ramp")
+                .long("ramp")
+                .value_name("SHAPE")
+                .use_delimiter(true)
+                .takes_value(true)
+                .possible_values(&["none", "linear", "raised-cosine"])
+                .default_value("none")
+                .help("Shape of the amplitude ramp at the start and end of each packet"),
+        )
+        .arg(
+            Arg::with_name("attack")
+                .long("attack")
+                .value_name("MSECS")
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_value("1")
+                .help("Length of the ramp at the start of each packet"),
+        )
+        .arg(
+            Arg::with_name("release")
+                .long("release")
+                .value_name("MSECS")
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_value("1")
+                .help("Length of the ramp at the end of each packet"),
+        )
+        .arg(
+            Arg::with_name("click-cutoff")
+                .long("click-cutoff")
+                .value_name("HZ")
+                .takes_value(true)
+                .help("Energy below this frequency is reported as clicks (default: F_LO / 2)"),
+        )
+        .arg(
+            Arg::with_name("phase-mode")
+                .long("phase-mode")
+                .value_name("MODE")
+                .use_delimiter(true)
+                .takes_value(true)
+                .possible_values(&["reset", "continuous", "random"])
+                .default_value("reset")
+                .help("Carrier phase at the start of each packet: reset to zero, continuous across packets and silence, or random"),
+        )
+        .arg(
+            Arg::with_name("hop-tones")
+                .long("hop-tones")
+                .value_name("F_LO:F_HI,...")
+                .takes_value(true)
+                .help("Enable frequency hopping between F_LO/F_HI and these additional tone pairs"),
+        )
+        .arg(
+            Arg::with_name("hop-interval")
+                .long("hop-interval")
+                .value_name("BYTES")
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_value("packet")
+                .help("Hop at the start of every packet, or every BYTES bytes"),
+        )
+        .arg(
+            Arg::with_name("hop-seed")
+                .long("hop-seed")
+                .value_name("SEED")
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_value("0")
+                .help("Seed of the hop sequence shared by the transmitter and receiver"),
+        )
+        .arg(
+            Arg::with_name("interferer")
+                .long("interferer")
+                .value_name("HZ:LEVEL")
+                .takes_value(true)
+                .help("Add a narrowband interferer at HZ with an amplitude of LEVEL (0.0 .. 1.0), after any --channel stages"),
+        )
+        .arg(
+            Arg::with_name("tone-gain")
+                .long("tone-gain")
+                .value_name("DB")
+                .use_delimiter(true)
+                .allow_hyphen_values(true)
+                .takes_value(true)
+                .default_value("0")
+                .help("Pre-emphasis gain of F_HI relative to F_LO, in dB"),
+        )
+        .arg(
+            Arg::with_name("equalizer")
+                .long("equalizer")
+                .value_name("FILENAME")
+                .use_delimiter(true)
+                .takes_value(true)
+                .help("CSV file of `frequency, gain_db` pairs measured from a speaker and microphone, whose inverse is applied as pre-emphasis"),
+        )
+        .arg(
+            Arg::with_name("emphasis-taps")
+                .long("emphasis-taps")
+                .value_name("TAPS")
+                .use_delimiter(true)
+                .takes_value(true)
+                .default_value("63")
+                .help("Length of the pre-emphasis FIR filter"),
+        )
+        .arg(
+            Arg::with_name("tx-dither")
+                .long("tx-dither")
+                .value_name("TYPE")
+                .use_delimiter(true)
+                .takes_value(true)
+                .possible_values(&["none", "rpdf", "tpdf"])
+                .default_value("none")
+                .help("Dither to add when the transmitter converts the waveform to 16 bits, before the channel: none, rectangular or triangular"),
+        )
         .arg(
             Arg::with_name("noise-level")
                 .long("noise")
                 .short("n")
                 .takes_value(true)
-                .help("Amount of noise to add (0.0 .. 1.0)")
+                .help("Standard deviation of Gaussian noise to add, relative to a full-scale signal of 1.0, after any --channel stages")
+        )
+        .arg(
+            Arg::with_name("snr")
+                .long("snr")
+                .value_name("DB")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .conflicts_with_all(&["noise-level", "ebn0"])
+                .help("Add Gaussian noise at this signal-to-noise ratio, after any --channel stages.  May be a range such as -5..1..20"),
+        )
+        .arg(
+            Arg::with_name("ebn0")
+                .long("ebn0")
+                .value_name("DB")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .conflicts_with_all(&["noise-level", "snr"])
+                .help("Add Gaussian noise at this Eb/N0, after any --channel stages.  May be a range such as 0..1..15"),
+        )
+        .arg(
+            Arg::with_name("channel")
+                .long("channel")
+                .value_name("STAGE:PARAM=VALUE,...")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help(channel_help),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("JOBS")
+                .takes_value(true)
+                .help("Number of points to run at once (default: the number of CPUs).  The results are written in the same order however many there are"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Carry on with a sweep that was interrupted, appending to its CSV log and skipping the points already in it.  Needs the same options and --seed as before"),
+        )
+        .arg(
+            Arg::with_name("trials")
+                .long("trials")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of times to run each point, each with its own seed.  The CSV log gets the mean success rate, its standard deviation and a 95% confidence interval"),
+        )
+        .arg(
+            Arg::with_name("ci-width")
+                .long("ci-width")
+                .value_name("WIDTH")
+                .takes_value(true)
+                .help("Stop running trials at a point once the 95% confidence interval of its success rate is narrower than WIDTH, such as 0.05.  --trials is then the most that will be run"),
+        )
+        .arg(
+            Arg::with_name("packet-log")
+                .long("packet-log")
+                .value_name("FILENAME")
+                .takes_value(true)
+                .help("Write a CSV file describing every packet sent: its type, block and pass, the sample it started at, whether the receiver found its sync word and passed its hash, and how many of its bits were wrong"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .takes_value(true)
+                .help("Seed for the order of the sweep and everything random within it.  Each point of the sweep derives its own seed from this one, and both are recorded in the CSV log so that any row can be replayed.  Chosen at random and printed if not given"),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Regenerate the waveform for a single point of a sweep.  Give the same options as the original sweep, with --seed set to the Sweep Seed column of the CSV log")
+                .arg(
+                    Arg::with_name("point")
+                        .value_name("POINT")
+                        .required(true)
+                        .help("The point to replay, from the Point column of the CSV log"),
+                )
+                .arg(
+                    Arg::with_name("trial")
+                        .long("trial")
+                        .value_name("TRIAL")
+                        .default_value("0")
+                        .help("The trial to replay, counting from zero"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILENAME")
+                        .default_value("replay.wav")
+                        .help("Name of the wave file to write the received waveform to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("optimize")
+                .about("Search the sweep for the combination that does best, rather than trying every one.  Each combination is run on every channel, and scored on how it does across all of them")
+                .arg(
+                    Arg::with_name("objective")
+                        .long("objective")
+                        .value_name("OBJECTIVE")
+                        .default_value("success")
+                        .help("What to make as large as possible: `success` for the mean success rate, `worst` for the lowest success rate on any channel, `throughput` for the success rate times the baud rate, or `reach:TARGET` for the fraction of channels that reach a success rate of TARGET, such as `reach:0.99` with --snr 0..2..30"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILENAME")
+                        .default_value("optimize.csv")
+                        .help("Name of the CSV file to log every combination tried to, in order"),
+                )
+                .arg(
+                    Arg::with_name("max-evaluations")
+                        .long("max-evaluations")
+                        .value_name("COUNT")
+                        .help("Stop after trying this many combinations, and report the best so far"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run the experiment described by a TOML file, and copy the file into the results directory")
+                .setting(AppSettings::TrailingVarArg)
+                .setting(AppSettings::AllowLeadingHyphen)
+                .arg(
+                    Arg::with_name("experiment")
+                        .value_name("EXPERIMENT")
+                        .required(true)
+                        .help("The experiment file to run"),
+                )
+                .arg(
+                    Arg::with_name("args")
+                        .value_name("ARGS")
+                        .multiple(true)
+                        .allow_hyphen_values(true)
+                        .help("Further options to add to the experiment, such as `-j 8` or `replay POINT`"),
+                ),
+        )
+}
 
-    let source_filename = matches.value_of("input").unwrap();
-    let target_filename = matches.value_of("output").unwrap_or("output.wav");
+fn main() -> Result<(), ModulationError> {
+    let channel_help = format!(
+        "Add a stage to the simulated channel between the transmitter and receiver.  Stages run in the order given.  STAGE may be {}.  Each VALUE may be a list of values separated by commas, and numeric values may be ranges as for the other options.  A directory sweeps over every wave file inside of it.  The transducer stage's speaker and microphone may be a CSV file of `frequency, gain_db` pairs, or one of {}.",
+        channel::stage_names().join(", "),
+        channel::profile_names().join(", "),
+    );
+    let matches = app(&channel_help).get_matches();
+    let experiment_args = match matches.subcommand_matches("run") {
+        Some(run) => {
+            let experiment = Experiment::load(run.value_of("experiment").unwrap())?;
+            experiment.save_copy()?;
+            let mut args = vec![std::env::args().next().unwrap_or_default()];
+            args.extend(experiment.args);
+            args.extend(
+                run.values_of("args")
+                    .into_iter()
+                    .flatten()
+                    .map(str::to_owned),
+            );
+            Some(args)
+        }
+        None => None,
+    };
+    let matches = match experiment_args {
+        Some(args) => app(&channel_help).get_matches_from(args),
+        None => matches,
+    };
+
+    let source_filename = match matches.value_of("input") {
+        Some(input) => input,
+        None => clap::Error::with_description(
+            "The following required arguments were not provided:\n    --input <FILENAME>",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let replay = matches.subcommand_matches("replay");
+    let replay_point = replay
+        .map(|replay| replay.value_of("point").unwrap().parse::<usize>())
+        .transpose()?;
+    let replay_trial = replay
+        .map(|replay| replay.value_of("trial").unwrap().parse::<usize>())
+        .transpose()?;
+    let trials = match replay_trial {
+        Some(trial) => trial..trial + 1,
+        None => 0..matches.value_of("trials").unwrap().parse::<usize>()?.max(1),
+    };
+    let ci_width = matches
+        .value_of("ci-width")
+        .map(|s| s.parse::<f64>())
+        .transpose()?
+        .filter(|_| replay.is_none());
+    let optimize = matches.subcommand_matches("optimize");
+    let objective = optimize
+        .map(|optimize| Objective::parse(optimize.value_of("objective").unwrap()))
+        .transpose()?;
+    let max_evaluations = optimize
+        .and_then(|optimize| optimize.value_of("max-evaluations"))
+        .map(|s| s.parse::<usize>())
+        .transpose()?;
+    let target_filename = match replay.or(optimize) {
+        Some(subcommand) => subcommand.value_of("output").unwrap(),
+        None => matches.value_of("output").unwrap_or("output.wav"),
+    };
+    let resume = matches.is_present("resume") && replay.is_none() && optimize.is_none();
+    if resume && !target_filename.ends_with(".csv") {
+        return Err(ModulationError::CantResume(
+            "only a CSV log can be resumed".to_owned(),
+        ));
+    }
+    if resume && !matches.is_present("seed") {
+        return Err(ModulationError::CantResume(
+            "--seed must be the same as the interrupted sweep's".to_owned(),
+        ));
+    }
+    let seed = matches
+        .value_of("seed")
+        .map(|s| s.parse::<u64>())
+        .transpose()?
+        .unwrap_or_else(rand::random);
+    let packet_log_filename = matches.value_of("packet-log");
     let os_update = matches.is_present("update");
     let play_file = matches.is_present("play");
-    let repeat_count = matches
-        .value_of("repeat-count")
-        .unwrap()
-        .parse::<u32>()
-        .unwrap();
-    let silence_prefix = matches
-        .value_of("silence-prefix")
-        .map(|s| s.parse::<u32>().unwrap());
-    let noise_level = matches
-        .value_of("noise-level")
-        .map(|s| s.parse::<f64>().unwrap())
-        .unwrap_or(0.0);
-    let output_sample_rate = if play_file {
+    let interferer = match matches.value_of("interferer") {
+        Some(spec) => {
+            let mut fields = spec.splitn(2, ':');
+            let frequency = fields.next().unwrap_or("").parse::<f64>()?;
+            let level = fields.next().unwrap_or("").parse::<f64>()?;
+            Some((frequency, level))
+        }
+        None => None,
+    };
+    let mut channel_specs = matches
+        .values_of("channel")
+        .map(|stages| stages.map(StageSpec::parse).collect::<Result<Vec<_>, _>>())
+        .transpose()?
+        .unwrap_or_default();
+    if let Some((frequency, level)) = interferer {
+        channel_specs.push(StageSpec::with_values(
+            "interferer",
+            &[("freq", frequency), ("level", level)],
+        )?);
+    }
+    if let Some(level) = matches.value_of("noise-level") {
+        if level.parse::<f64>().ok() != Some(0.0) {
+            channel_specs.push(StageSpec::parse(&format!("noise:level={}", level))?);
+        }
+    }
+    if let Some(snr) = matches.value_of("snr") {
+        channel_specs.push(StageSpec::parse(&format!("noise:snr={}", snr))?);
+    }
+    if let Some(ebn0) = matches.value_of("ebn0") {
+        channel_specs.push(StageSpec::parse(&format!("noise:ebn0={}", ebn0))?);
+    }
+    let channels = Channel::variants(&channel_specs)?;
+    let click_cutoff = matches
+        .value_of("click-cutoff")
+        .map(|s| s.parse::<f64>())
+        .transpose()?;
+    // Playing the sound forces the sample rate to whatever the sound device
+    // supports.  Otherwise it's swept like anything else, with a default.
+    let fixed_sample_rate = if play_file {
         let endpoint = cpal::default_endpoint().expect("Failed to get default endpoint");
         let format = endpoint
             .supported_formats()
@@ -377,138 +1635,248 @@ fn main() -> Result<(), ModulationError> {
             .next()
             .expect("Failed to get endpoint format")
             .with_max_samples_rate();
-        format.samples_rate.0 as f64
+        Some(format.samples_rate.0.to_string())
+    } else if !matches.is_present("sample-rate") {
+        Some(DEFAULT_SAMPLE_RATE.to_string())
     } else {
-        matches
-            .value_of("sample-rate")
-            .map(|s| s.parse::<f64>().unwrap())
-            .unwrap_or(DEFAULT_SAMPLE_RATE)
+        None
     };
-    let baud_rate = matches
-        .value_of("baud-rate")
-        .map(|s| SteppedRange::parse(s))
-        .unwrap()?;
-    let f_lo = matches
-        .value_of("f-lo")
-        .map(|s| SteppedRange::parse(s))
-        .unwrap()?;
-    let f_hi = matches
-        .value_of("f-hi")
-        .map(|s| SteppedRange::parse(s))
-        .unwrap()?;
-    let protocol_version = match matches.value_of("version") {
-        Some("1") => controller::ProtocolVersion::V1,
-        Some("2") => controller::ProtocolVersion::V2,
-        Some(x) => panic!("Unrecognized version found: {}", x),
-        None => panic!("No protocol version specified"),
+    let mut dimensions = vec![];
+    for (name, column, kind) in DIMENSIONS {
+        let given = match (*name, &fixed_sample_rate) {
+            ("sample-rate", Some(rate)) => Some(vec![rate.as_str()]),
+            _ => matches.values_of(name).map(|values| values.collect()),
+        };
+        dimensions.push(Dimension::new(
+            name,
+            column,
+            *kind,
+            given.map(|values| values.into_iter()),
+        )?);
+    }
+    let sweep = Sweep::new(dimensions);
+
+    // Each equalizer is read once here, rather than at every point using it
+    let mut equalizers = HashMap::new();
+    for path in sweep.values("equalizer") {
+        if path != "none" && !equalizers.contains_key(path) {
+            equalizers.insert(path.clone(), dsp::FrequencyResponse::load(path)?);
+        }
+    }
+
+    println!("Modulating {} into {}.", source_filename, target_filename);
+    println!("Is update? {}", os_update);
+
+    // Playing never returns, so there's no sense in starting more than one
+    let jobs = match matches.value_of("jobs") {
+        _ if play_file => 1,
+        Some(jobs) => jobs.parse::<usize>()?.max(1),
+        None => std::thread::available_parallelism()
+            .map(|jobs| jobs.get())
+            .unwrap_or(1),
     };
-    let filter_width = SteppedRange::parse(matches.value_of("filter-width").unwrap())?;
-    let data_rate = match matches.value_of("encoding-rate") {
-        Some("low") => EncodingRate::Low,
-        Some("mid") => EncodingRate::Mid,
-        Some("high") => EncodingRate::High,
-        Some(x) => panic!("Unrecognized rate found: {}", x),
-        None => panic!("No valid rate specified"),
+    println!("Seed: {}", seed);
+
+    let runner = Runner {
+        source_filename,
+        sweep: &sweep,
+        channels: &channels,
+        equalizers: &equalizers,
+        seed,
+        os_update,
+        click_cutoff,
+        play_file,
+        // A replay demodulates too, so the result can be checked against the
+        // original CSV row
+        demodulate: target_filename.ends_with(".csv")
+            || replay.is_some()
+            || objective.is_some()
+            || packet_log_filename.is_some(),
+        keep_output: !target_filename.ends_with(".csv") && objective.is_none(),
+        trials,
+        ci_width,
+        packet_log: packet_log_filename.is_some(),
     };
+    if let Some(objective) = objective {
+        let mut output_file = File::create(target_filename)?;
+        return optimize_sweep(&runner, objective, max_evaluations, jobs, &mut output_file);
+    }
 
-    println!("Modulating {} into {}.", source_filename, target_filename);
-    println!(
-        "Is update? {}  Data rate: {}  Protocol version: {:?}",
-        os_update, data_rate, protocol_version
+    let mut header = sweep.columns().join(", ");
+    header.push_str(", Modulation Rate, RX Baud Rate, RX F_LO, RX F_HI, RX Filter Width");
+    header.push_str(", Click Energy (dB), Sweep Seed, Point, Seed");
+    for column in channels[0].columns() {
+        header.push_str(", ");
+        header.push_str(&column);
+    }
+    header.push_str(
+        ", Total Packets, Packets Decoded, Success Rate, Trials, Success Std Dev, CI Low, CI High",
     );
+    header.push_str(", Bits Sent, Bit Errors, BER, Bit Slips, Sync Losses");
 
-    let mut cfg = ModulationConfig {
-        data_rate,
-        os_update,
-        baud_rate: baud_rate.start as _,
-        f_lo: f_lo.start as _,
-        f_hi: f_hi.start as _,
-        silence_prefix,
-        version: protocol_version,
-        repeat_count: repeat_count,
-        sample_rate: output_sample_rate,
+    // Resuming picks up where an interrupted sweep left off, appending to
+    // its log rather than starting it over
+    let resuming = resume && std::path::Path::new(target_filename).exists();
+    let completed = if resuming {
+        completed_points(target_filename, &header, &sweep, &channels, seed)?
+    } else {
+        Default::default()
+    };
+    let mut output_file = if resuming {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(target_filename)?
+    } else {
+        File::create(target_filename)?
+    };
+    if target_filename.ends_with(".csv") && output_file.metadata()?.len() == 0 {
+        writeln!(output_file, "{}", header)?;
+    }
+    let mut packet_log = match packet_log_filename {
+        Some(filename) => {
+            if resuming {
+                trim_packet_log(filename, &completed)?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(resuming)
+                .write(true)
+                .truncate(!resuming)
+                .open(filename)?;
+            if file.metadata()?.len() == 0 {
+                writeln!(
+                    file,
+                    "Point, Trial, Packet, Type, Block, Pass, Start Sample, Sync Found, Hash Passed, Bits, Bit Errors, Bit Slips"
+                )?;
+            }
+            Some(file)
+        }
+        None => None,
     };
 
-    let mut output_file = File::create(target_filename)?;
-    if target_filename.ends_with(".csv") {
-        writeln!(output_file, "Noise Level, Baud Rate, F_LO, F_HI, Filter Width, Sample Rate, Total Packets, Packets Decoded, Success Rate").unwrap();
+    // The channel varies fastest, as if it were the last dimension
+    let point_count = sweep.point_count() * channels.len();
+    let mut points: Vec<usize> = match replay_point {
+        Some(point) if point >= point_count => {
+            return Err(ModulationError::NoSuchPoint(point, point_count))
+        }
+        Some(point) => vec![point],
+        None => (0..point_count).collect(),
+    };
+    // The order is shuffled before skipping finished points, so that the
+    // rest run in the same order they would have done without interruption
+    points.shuffle(&mut StdRng::seed_from_u64(seed));
+    if !completed.is_empty() {
+        points.retain(|point| !completed.contains(point));
+        println!(
+            "Resuming with {} of {} combinations done",
+            completed.len(),
+            point_count
+        );
     }
+    println!("Will try {} combinations with {} jobs", points.len(), jobs);
 
-    let mut rng = rand::thread_rng();
-    let all_params = iproduct!(baud_rate, f_lo, f_hi, filter_width);
-    let mut all_params_shuffled: Vec<(u32, u32, u32, u32)> = all_params.collect();
-    all_params_shuffled.shuffle(&mut rng);
-    println!("Will try {} combinations", all_params_shuffled.len());
-    for (idx, (baud_rate, f_lo, f_hi, filter_width)) in all_params_shuffled.iter().enumerate() {
-        cfg.baud_rate = *baud_rate as _;
-        cfg.f_lo = *f_lo as _;
-        cfg.f_hi = *f_hi as _;
+    // Points finish in whatever order the workers get to them, so hold on to
+    // each result until the ones before it have been written
+    let mut finished = BTreeMap::new();
+    let mut written = 0;
+    let mut completed = 0;
+    run_points(&runner, &points, jobs, |idx, result| {
+        completed += 1;
         print!(
-            "{:<.4}% PARAMETERS   noise: {:<3}  baud_rate: {:<6}  f_lo: {:<6}  f_hi: {:<6}  filter_width: {:<2}  ",
-            idx as f64 / all_params_shuffled.len() as f64 * 100.0,
-            noise_level, cfg.baud_rate, cfg.f_lo, cfg.f_hi, filter_width
+            "{:<.4}% {}",
+            completed as f64 / points.len() as f64 * 100.0,
+            result.report
         );
-        let (audio_data, packet_count) = do_modulation(source_filename, &cfg)?;
-
-        if play_file {
-            do_play_file(audio_data, output_sample_rate);
-        }
-        let mut output: Vec<i16> = Vec::new();
-        for mut sample in audio_data {
-            if noise_level > 0.0 {
-                let mut rng_sample: f64 = rng.sample(StandardNormal);
-                rng_sample *= 2.0; // (0,1) -> (0,2)
-                rng_sample -= 1.0; // (0,2) -> (-1,1)
-                rng_sample *= noise_level; // (-1,1) -> (-noise_level,noise_level)
-                sample += rng_sample;
-
-                // Clamp the sample to the range (-1,1)
-                if sample > 1.0 {
-                    sample = 1.0;
-                }
-                if sample < -1.0 {
-                    sample = -1.0;
+        finished.insert(idx, result);
+        while let Some(result) = finished.remove(&written) {
+            // The packets go first, so a point only counts as done for
+            // --resume once they're all logged
+            if let Some(packet_log) = packet_log.as_mut() {
+                for row in result.packet_rows {
+                    writeln!(packet_log, "{}", row)?;
                 }
             }
-            // Map -1 .. 1 to -32767 .. 32768
-            output.push((sample * 32767.0).round() as i16);
+            if let Some(row) = result.row.filter(|_| target_filename.ends_with(".csv")) {
+                writeln!(output_file, "{}", row)?;
+            }
+            for output in result.outputs {
+                wav::write_wav(result.sample_rate, &output, &mut output_file)?;
+            }
+            written += 1;
         }
+        Ok(())
+    })
+}
 
-        if target_filename.ends_with(".csv") {
-            let ccfg = ModulationConfigC {
-                sample_rate: cfg.sample_rate as _,
-                f_lo: cfg.f_lo as _,
-                f_hi: cfg.f_hi as _,
-                filter_width: *filter_width as _,
-                baud_rate: cfg.baud_rate as _,
-            };
-            let successes =
-                unsafe { attempt_demodulation(&ccfg, output.as_ptr(), output.len() as u32) };
-            println!(
-                "DEMOD  {:2}/{:<2} {:.3}%",
-                successes,
-                packet_count,
-                (successes as f64) / (packet_count as f64) * 100.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The modulation config for a single point given on the command line
+    fn config(args: &[&str]) -> ModulationConfig {
+        let args = ["nus-harness", "-i", "test/reference.bin"]
+            .iter()
+            .chain(args);
+        let matches = app("").get_matches_from(args);
+        let dimensions = DIMENSIONS
+            .iter()
+            .map(|(name, column, kind)| {
+                Dimension::new(name, column, *kind, matches.values_of(name)).unwrap()
+            })
+            .collect();
+        let sweep = Sweep::new(dimensions);
+        modulation_config(&sweep.point(0), false, &HashMap::new()).unwrap()
+    }
+
+    /// Send the reference file over a perfect channel, returning how many
+    /// packets were sent and how many the receiver got
+    fn decode(cfg: &ModulationConfig) -> (usize, usize) {
+        let transmission = do_modulation("test/reference.bin", cfg).unwrap();
+        let samples: Vec<i16> = transmission
+            .audio
+            .iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16)
+            .collect();
+        let (successes, _) = demodulate(&cfg.receiver_config(8), &transmission.hops, &samples);
+        (transmission.packet_count, successes)
+    }
+
+    #[test]
+    fn every_encoding_rate_decodes_on_a_clean_channel() {
+        for rate in ["high", "mid", "low"] {
+            let cfg = config(&["--rate", "44100", "--encoding-rate", rate]);
+            let (sent, received) = decode(&cfg);
+            assert!(
+                received + 1 >= sent,
+                "{} rate: {} of {} packets",
+                rate,
+                received,
+                sent
             );
-            writeln!(
-                output_file,
-                "{}, {}, {}, {}, {}, {}, {}, {}, {}",
-                noise_level,
-                baud_rate,
-                f_lo,
-                f_hi,
-                filter_width,
-                output_sample_rate,
-                packet_count,
-                successes,
-                (successes as f64) / (packet_count as f64)
-            )
-            .unwrap();
-        } else {
-            println!("Appending to wave file {}", target_filename);
-            wav::write_wav(cfg.sample_rate as u32, &output, &mut output_file)?;
         }
     }
 
-    Ok(())
+    #[test]
+    fn receiver_widens_filter_and_rounds_tones_for_slower_rates() {
+        let ccfg = config(&["--rate", "44100", "--encoding-rate", "low"]).receiver_config(8);
+        assert_eq!(ccfg.filter_width, 32);
+        assert_eq!((ccfg.baud_rate, ccfg.f_lo, ccfg.f_hi), (2000, 2167, 3125));
+    }
+
+    #[test]
+    fn run_passes_options_through_to_the_experiment() {
+        for args in [
+            &["run", "e.toml", "-j", "8", "replay", "3"][..],
+            &["run", "e.toml", "--jobs=8", "replay", "3"],
+            &["run", "e.toml", "--", "-j", "8", "replay", "3"],
+        ] {
+            let matches = app("").get_matches_from(["nus-harness"].iter().chain(args));
+            let run = matches.subcommand_matches("run").unwrap();
+            assert_eq!(run.value_of("experiment"), Some("e.toml"));
+            let extra: Vec<&str> = run.values_of("args").unwrap().collect();
+            assert_eq!(extra.last(), Some(&"3"), "{:?}", args);
+            assert!(extra.contains(&"replay"));
+        }
+    }
 }
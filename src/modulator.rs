@@ -1,17 +1,190 @@
+use rand::prelude::*;
+
 use crate::fsk;
 
+/// What happens to the carrier phase at the start of each packet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhaseMode {
+    /// Start every packet with a phase of zero.  The bit clock runs on from
+    /// the previous packet, which ends a sample early, so each packet opens
+    /// with part of a bit of the tone the one before it finished on.
+    Reset,
+
+    /// Keep the phase and bit timing running across packets and silence, as
+    /// if the transmitter had never stopped
+    Continuous,
+
+    /// Start every packet at a random phase
+    Random,
+}
+
+impl core::fmt::Display for PhaseMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            PhaseMode::Reset => write!(f, "reset"),
+            PhaseMode::Continuous => write!(f, "continuous"),
+            PhaseMode::Random => write!(f, "random"),
+        }
+    }
+}
+
 pub struct Modulator {
     encoder: fsk::FskEncoder,
+    phase_mode: PhaseMode,
+    rng: StdRng,
 }
 
 impl Modulator {
     pub fn new(sample_rate: f64, baud_rate: f64, f_lo: f64, f_hi: f64) -> Modulator {
-        Modulator { encoder: fsk::FskEncoder::new(f_lo, f_hi, baud_rate, sample_rate) }
+        Modulator {
+            encoder: fsk::FskEncoder::new(f_lo, f_hi, baud_rate, sample_rate),
+            phase_mode: PhaseMode::Reset,
+            rng: StdRng::seed_from_u64(0),
+        }
     }
 
-    // Modulate an array of 8-bit bytes into an array of signed 16-bit PCM samples
-    pub fn modulate_pcm(&mut self, input: &[u8], output: &mut Vec<f64>) {
-        self.encoder.reset();
-        self.encoder.modulate(input, output)
+    /// Seed the generator behind the random phase mode, so that a run can be
+    /// repeated exactly.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_phase_mode(&mut self, phase_mode: PhaseMode) {
+        self.phase_mode = phase_mode;
+    }
+
+    // Account for `count` samples of silence between packets.  Only the
+    // continuous phase mode cares about how long the transmitter was quiet.
+    pub fn skip(&mut self, count: usize) {
+        if self.phase_mode == PhaseMode::Continuous {
+            self.encoder.advance(count);
+        }
+    }
+
+    pub fn set_tones(&mut self, f_lo: f64, f_hi: f64) {
+        self.encoder.set_tones(f_lo, f_hi);
+    }
+
+    // Start a new burst, setting the phase according to the phase mode.  The
+    // burst opens with `lead_in` samples of the space tone, which gives an
+    // envelope somewhere to ramp without eating into the preamble.  The
+    // receiver hears the lead-in as more of the zeroes the preamble starts
+    // with, so it has no edges to throw the receiver's bit clock off, and the
+    // packet that follows is sent just as it would be without one.
+    pub fn begin_burst(&mut self, lead_in: usize, output: &mut Vec<f64>) {
+        match self.phase_mode {
+            PhaseMode::Reset => self.encoder.reset(),
+            PhaseMode::Continuous => (),
+            PhaseMode::Random => self
+                .encoder
+                .set_phase(self.rng.gen_range(0.0, 2.0 * std::f64::consts::PI)),
+        }
+        if lead_in > 0 {
+            self.encoder.hold(0, lead_in, output);
+            if self.phase_mode == PhaseMode::Continuous {
+                self.encoder.advance_clock(lead_in);
+            }
+        }
+    }
+
+    // Continue the current burst with more data.  Consecutive calls are
+    // seamless, so a packet may be split up in order to retune partway through.
+    pub fn modulate_bytes(&mut self, input: &[u8], output: &mut Vec<f64>) {
+        self.encoder.modulate(input, output);
+    }
+
+    // Finish the current burst, whose final byte was `last_byte`, with `tail`
+    // samples of whichever tone it finished on.
+    pub fn end_burst(&mut self, last_byte: u8, tail: usize, output: &mut Vec<f64>) {
+        if self.phase_mode != PhaseMode::Continuous {
+            self.encoder.drop_sample();
+        }
+        if tail > 0 {
+            self.encoder.hold(last_byte >> 7, tail, output);
+            if self.phase_mode == PhaseMode::Continuous {
+                self.encoder.advance_clock(tail);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The encoder as it was before phase modes, which reset the phase for
+    /// each packet and let the bit clock run on from the last one
+    fn baseline(packets: &[&[u8]], sample_rate: f64, baud_rate: f64) -> Vec<f64> {
+        let (omega_lo, omega_hi) = (
+            2.0 * std::f64::consts::PI * 8666.0 / sample_rate,
+            2.0 * std::f64::consts::PI * 12500.0 / sample_rate,
+        );
+        let baud_incr = baud_rate / sample_rate;
+        let (mut baud_frac, mut current_bit, mut current_byte, mut bit_pos) = (0.0, 0, 0u8, 0);
+        let mut output = vec![];
+        for packet in packets {
+            let mut phase = 0.0f64;
+            let mut data_pos = 0;
+            loop {
+                baud_frac += baud_incr;
+                if baud_frac >= 1.0 {
+                    baud_frac -= 1.0;
+                    if bit_pos == 0 {
+                        if data_pos < packet.len() {
+                            current_byte = packet[data_pos];
+                            data_pos += 1;
+                            bit_pos = 8;
+                        } else {
+                            break;
+                        }
+                    }
+                    current_bit = current_byte & 1;
+                    current_byte >>= 1;
+                    bit_pos -= 1;
+                }
+                output.push(phase.sin());
+                phase += if current_bit == 0 { omega_lo } else { omega_hi };
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn reset_matches_baseline_encoder() {
+        let packets: [&[u8]; 3] = [&[0, 0, 0x55, 0xd3, 0x91], &[0xff, 0x01, 0x80], &[0x3c; 7]];
+        let mut modulator = Modulator::new(44100.0, 8000.0, 8666.0, 12500.0);
+        let mut output = vec![];
+        for packet in &packets {
+            modulator.begin_burst(0, &mut output);
+            modulator.modulate_bytes(packet, &mut output);
+            modulator.end_burst(*packet.last().unwrap(), 0, &mut output);
+        }
+        assert_eq!(output, baseline(&packets, 44100.0, 8000.0));
+    }
+
+    #[test]
+    fn lead_in_leaves_the_packet_alone() {
+        // Apart from the carrier having moved on, every bit of each packet,
+        // preamble included, goes out the same with a lead-in as without
+        let packet = [0, 0, 0, 0, 0x55, 0xd3, 0x91];
+        let (lead_in, tail) = (23, 17);
+        let omega_lo = 2.0 * std::f64::consts::PI * 8666.0 / 44100.0;
+        let mut ramped = Modulator::new(44100.0, 8000.0, 8666.0, 12500.0);
+        let mut plain = Modulator::new(44100.0, 8000.0, 8666.0, 12500.0);
+        for _ in 0..3 {
+            let (mut with, mut without) = (vec![], vec![]);
+            ramped.begin_burst(lead_in, &mut with);
+            ramped.modulate_bytes(&packet, &mut with);
+            ramped.end_burst(0x91, tail, &mut with);
+            plain.begin_burst(0, &mut without);
+            plain.encoder.set_phase(omega_lo * lead_in as f64);
+            plain.modulate_bytes(&packet, &mut without);
+            plain.end_burst(0x91, 0, &mut without);
+
+            assert_eq!(with.len(), lead_in + without.len() + tail);
+            for (n, (a, b)) in with[lead_in..].iter().zip(&without).enumerate() {
+                assert!((a - b).abs() < 1e-9, "sample {}: {} != {}", n, a, b);
+            }
+        }
     }
 }
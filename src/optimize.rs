@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+pub enum OptimizeError {
+    UnknownObjective(String),
+    BadTarget(String),
+}
+
+impl core::fmt::Debug for OptimizeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            OptimizeError::UnknownObjective(s) => write!(f, "unknown objective {}", s),
+            OptimizeError::BadTarget(s) => {
+                write!(f, "target {} is not a success rate between 0 and 1", s)
+            }
+        }
+    }
+}
+
+/// What the optimizer tries to make as large as possible.  Each is worked
+/// out from the results of a setting on every channel given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Objective {
+    /// The mean success rate
+    Success,
+
+    /// The lowest success rate on any channel, for a setting that holds up
+    /// everywhere
+    Worst,
+
+    /// The mean of the success rate multiplied by the baud rate, which
+    /// trades reliability for speed
+    Throughput,
+
+    /// The fraction of channels on which the success rate reaches the
+    /// target.  With channels at a range of SNRs, this finds the setting
+    /// that still works at the lowest SNR.
+    Reach(f64),
+}
+
+impl Objective {
+    pub fn parse(spec: &str) -> Result<Objective, OptimizeError> {
+        let mut fields = spec.splitn(2, ':');
+        match (fields.next().unwrap_or(""), fields.next()) {
+            ("success", None) => Ok(Objective::Success),
+            ("worst", None) => Ok(Objective::Worst),
+            ("throughput", None) => Ok(Objective::Throughput),
+            ("reach", Some(target)) => match target.parse::<f64>() {
+                Ok(target) if target > 0.0 && target <= 1.0 => Ok(Objective::Reach(target)),
+                _ => Err(OptimizeError::BadTarget(target.to_owned())),
+            },
+            _ => Err(OptimizeError::UnknownObjective(spec.to_owned())),
+        }
+    }
+
+    /// Score a setting from its success rate and baud rate on each channel
+    pub fn score(self, results: &[(f64, f64)]) -> f64 {
+        let count = results.len() as f64;
+        match self {
+            Objective::Success => results.iter().map(|(rate, _)| rate).sum::<f64>() / count,
+            Objective::Worst => results
+                .iter()
+                .map(|(rate, _)| *rate)
+                .fold(f64::INFINITY, f64::min),
+            Objective::Throughput => {
+                results.iter().map(|(rate, baud)| rate * baud).sum::<f64>() / count
+            }
+            Objective::Reach(target) => {
+                results.iter().filter(|(rate, _)| *rate >= target).count() as f64 / count
+            }
+        }
+    }
+}
+
+/// Search a grid with `sizes[d]` values along each dimension for the point
+/// where `evaluate` is largest, without trying every point.  This is a
+/// compass search: starting from the middle of the grid, it tries a step
+/// either way along each dimension in turn, moving whenever that does
+/// better and halving the step when it doesn't, until no step of one does
+/// any better.
+///
+/// `evaluate` is given a batch of points, each as an index into every
+/// dimension, so that they can be run together.  No point is evaluated
+/// twice, and no more than `max_evaluations` points are evaluated in all,
+/// if given.  Returns the best point and its score.
+pub fn compass_search<E>(
+    sizes: &[usize],
+    max_evaluations: Option<usize>,
+    mut evaluate: impl FnMut(&[Vec<usize>]) -> Result<Vec<f64>, E>,
+) -> Result<(Vec<usize>, f64), E> {
+    let mut current: Vec<usize> = sizes.iter().map(|size| size / 2).collect();
+    let mut best = evaluate(&[current.clone()])?[0];
+    let mut remaining = max_evaluations.map(|max| max.saturating_sub(1));
+    let mut seen = HashMap::new();
+    seen.insert(current.clone(), best);
+    let mut steps: Vec<usize> = sizes.iter().map(|size| (size / 4).max(1)).collect();
+
+    loop {
+        let mut changed = false;
+        for dimension in 0..sizes.len() {
+            let step = steps[dimension];
+            let mut candidates = vec![];
+            if current[dimension] >= step {
+                let mut candidate = current.clone();
+                candidate[dimension] -= step;
+                candidates.push(candidate);
+            }
+            if current[dimension] + step < sizes[dimension] {
+                let mut candidate = current.clone();
+                candidate[dimension] += step;
+                candidates.push(candidate);
+            }
+            // Anything already tried did no better than the current point,
+            // or the search would have moved there
+            candidates.retain(|candidate| !seen.contains_key(candidate));
+            if let Some(remaining) = remaining.as_mut() {
+                if *remaining == 0 {
+                    return Ok((current, best));
+                }
+                candidates.truncate(*remaining);
+                *remaining -= candidates.len();
+            }
+
+            let scores = if candidates.is_empty() {
+                vec![]
+            } else {
+                evaluate(&candidates)?
+            };
+            let mut improved = false;
+            for (candidate, score) in candidates.into_iter().zip(scores) {
+                if score > best {
+                    current = candidate.clone();
+                    best = score;
+                    improved = true;
+                }
+                seen.insert(candidate, score);
+            }
+            if improved {
+                changed = true;
+            } else if step > 1 {
+                steps[dimension] = step / 2;
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok((current, best));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a search over `objective`, returning the best point and its
+    /// score along with every batch of points evaluated
+    fn search(
+        sizes: &[usize],
+        max_evaluations: Option<usize>,
+        objective: impl Fn(&[usize]) -> f64,
+    ) -> (Vec<usize>, f64, Vec<Vec<Vec<usize>>>) {
+        let mut batches = vec![];
+        let (point, score) = compass_search::<()>(sizes, max_evaluations, |candidates| {
+            batches.push(candidates.to_vec());
+            Ok(candidates.iter().map(|c| objective(c)).collect())
+        })
+        .unwrap();
+        (point, score, batches)
+    }
+
+    #[test]
+    fn finds_the_peak_of_one_dimension() {
+        for peak in [0, 3, 37, 50, 99] {
+            let (point, score, batches) =
+                search(&[100], None, |x| -(x[0] as f64 - peak as f64).abs());
+            assert_eq!(point, vec![peak]);
+            assert_eq!(score, 0.0);
+            let evaluated: usize = batches.iter().map(|b| b.len()).sum();
+            assert!(evaluated < 50, "{} points evaluated", evaluated);
+        }
+    }
+
+    #[test]
+    fn finds_the_peak_of_two_dimensions() {
+        let objective = |x: &[usize]| {
+            let dx = x[0] as f64 - 13.0;
+            let dy = x[1] as f64 - 28.0;
+            -(dx * dx + 2.0 * dy * dy)
+        };
+        let (point, score, batches) = search(&[40, 33], None, objective);
+        assert_eq!(point, vec![13, 28]);
+        assert_eq!(score, 0.0);
+
+        let mut evaluated: Vec<&Vec<usize>> = batches.iter().flatten().collect();
+        let count = evaluated.len();
+        evaluated.sort();
+        evaluated.dedup();
+        assert_eq!(evaluated.len(), count, "a point was evaluated twice");
+    }
+
+    #[test]
+    fn stops_at_the_evaluation_budget() {
+        for budget in 1..12 {
+            let (point, score, batches) =
+                search(&[1000, 1000], Some(budget), |x| (x[0] + x[1]) as f64);
+            let evaluated: Vec<&Vec<usize>> = batches.iter().flatten().collect();
+            assert_eq!(evaluated.len(), budget);
+            // The best point found is still returned
+            let best = evaluated.iter().max_by_key(|x| x[0] + x[1]).unwrap();
+            assert_eq!(&&point, best);
+            assert_eq!(score, (point[0] + point[1]) as f64);
+        }
+    }
+
+    #[test]
+    fn halves_the_step_when_nothing_does_better() {
+        // The start is already the best point, so every step fails and the
+        // search tries ever closer neighbours: 16, 8, 4, 2 then 1 away
+        let (point, _, batches) = search(&[64], None, |x| -(x[0] as f64 - 32.0).abs());
+        assert_eq!(point, vec![32]);
+        let steps: Vec<Vec<usize>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|x| x[0]).collect())
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                vec![32],
+                vec![16, 48],
+                vec![24, 40],
+                vec![28, 36],
+                vec![30, 34],
+                vec![31, 33],
+            ]
+        );
+    }
+}
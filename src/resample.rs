@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+/// Number of zero crossings of the sinc kernel on either side of its centre.
+/// Higher values give a sharper anti-aliasing filter at the cost of speed.
+const ZERO_CROSSINGS: f64 = 16.0;
+
+/// Shape parameter of the Kaiser window applied to the sinc kernel
+const KAISER_BETA: f64 = 8.6;
+
+/// Fraction of the Nyquist frequency that the anti-aliasing filter passes
+const ROLLOFF: f64 = 0.95;
+
+/// Zeroth-order modified Bessel function of the first kind, used by the
+/// Kaiser window.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..50 {
+        term *= (half_x / k as f64) * (half_x / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Number of entries in the kernel lookup table per zero crossing
+const TABLE_RESOLUTION: usize = 512;
+
+/// A band-limited interpolator that can read a signal at any fractional
+/// sample position.
+pub struct Interpolator {
+    /// Cutoff of the low-pass filter, as a fraction of the input Nyquist
+    cutoff: f64,
+
+    /// How many input samples the kernel extends on either side
+    half_width: f64,
+
+    /// One side of the windowed sinc, indexed by zero crossing
+    table: Vec<f64>,
+}
+
+impl Interpolator {
+    /// Create an interpolator for reading a signal at `ratio` output samples
+    /// per input sample.  When decimating, the filter is narrowed to avoid
+    /// aliasing.
+    pub fn new(ratio: f64) -> Interpolator {
+        let cutoff = ratio.min(1.0) * ROLLOFF;
+        let window_norm = bessel_i0(KAISER_BETA);
+        let table_len = ZERO_CROSSINGS as usize * TABLE_RESOLUTION + 2;
+        let table = (0..table_len)
+            .map(|i| {
+                let x = i as f64 / TABLE_RESOLUTION as f64;
+                if x >= ZERO_CROSSINGS {
+                    return 0.0;
+                }
+                let sinc = if i == 0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let r = x / ZERO_CROSSINGS;
+                sinc * bessel_i0(KAISER_BETA * (1.0 - r * r).sqrt()) / window_norm
+            })
+            .collect();
+        Interpolator {
+            cutoff,
+            half_width: ZERO_CROSSINGS / cutoff,
+            table,
+        }
+    }
+
+    fn kernel(&self, t: f64) -> f64 {
+        let x = t.abs() * self.cutoff * TABLE_RESOLUTION as f64;
+        let index = x as usize;
+        if index + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let frac = x - index as f64;
+        let value = self.table[index] + (self.table[index + 1] - self.table[index]) * frac;
+        self.cutoff * value
+    }
+
+    /// Return the value of `input` at the fractional sample `position`.
+    /// Samples outside of `input` are treated as silence.
+    pub fn sample(&self, input: &[f64], position: f64) -> f64 {
+        let first = (position - self.half_width).ceil().max(0.0) as usize;
+        let last =
+            ((position + self.half_width).floor() as usize).min(input.len().saturating_sub(1));
+        if input.is_empty() || first > last {
+            return 0.0;
+        }
+        (first..=last)
+            .map(|k| input[k] * self.kernel(position - k as f64))
+            .sum()
+    }
+}
+
+/// Convert `input` from `from_rate` to `to_rate`.
+pub fn resample(input: &[f64], from_rate: f64, to_rate: f64) -> Vec<f64> {
+    if (from_rate - to_rate).abs() < f64::EPSILON {
+        return input.to_vec();
+    }
+    let ratio = to_rate / from_rate;
+    let interpolator = Interpolator::new(ratio);
+    let output_len = (input.len() as f64 * ratio).round() as usize;
+    (0..output_len)
+        .map(|n| interpolator.sample(input, n as f64 / ratio))
+        .collect()
+}
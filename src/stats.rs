@@ -0,0 +1,92 @@
+/// The normal quantile for a two-sided 95% confidence interval
+pub const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Mean and sample standard deviation.  A single value has no spread, so its
+/// standard deviation is zero.
+pub fn mean_and_std_dev(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance.sqrt())
+}
+
+/// Wilson score interval for a proportion of `successes` out of `total`.
+/// Unlike the normal approximation, it stays within [0, 1] and behaves
+/// sensibly when every packet, or none of them, gets through.
+pub fn wilson_interval(successes: usize, total: usize, z: f64) -> (f64, f64) {
+    if total == 0 {
+        return (0.0, 1.0);
+    }
+    let n = total as f64;
+    let p = successes as f64 / n;
+    let z2 = z * z;
+    let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half_width = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    // At either edge the bound is exact, but rounding would leave it a hair
+    // away from 0 or 1
+    let low = if successes == 0 {
+        0.0
+    } else {
+        (centre - half_width).clamp(0.0, 1.0)
+    };
+    let high = if successes >= total {
+        1.0
+    } else {
+        (centre + half_width).clamp(0.0, 1.0)
+    };
+    (low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn mean_and_std_dev_of_known_values() {
+        assert_eq!(mean_and_std_dev(&[]), (0.0, 0.0));
+        assert_eq!(mean_and_std_dev(&[0.75]), (0.75, 0.0));
+        let (mean, std_dev) = mean_and_std_dev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_close(mean, 5.0);
+        assert_close(std_dev, (32.0f64 / 7.0).sqrt());
+    }
+
+    #[test]
+    fn wilson_interval_of_known_proportions() {
+        let (low, high) = wilson_interval(5, 10, 1.96);
+        assert_close(low, 0.2366);
+        assert_close(high, 0.7634);
+
+        let (low, high) = wilson_interval(0, 10, 1.96);
+        assert_eq!(low, 0.0);
+        assert_close(high, 0.2775);
+
+        let (low, high) = wilson_interval(10, 10, 1.96);
+        assert_close(low, 0.7225);
+        assert_eq!(high, 1.0);
+
+        assert_eq!(wilson_interval(0, 0, Z_95), (0.0, 1.0));
+    }
+
+    #[test]
+    fn wilson_interval_edges_are_exact_for_any_count() {
+        for total in 1..200 {
+            assert_eq!(wilson_interval(0, total, Z_95).0, 0.0);
+            assert_eq!(wilson_interval(total, total, Z_95).1, 1.0);
+        }
+    }
+}
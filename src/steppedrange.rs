@@ -1,20 +1,59 @@
 use std::num;
-use std::iter::Iterator;
 
-#[derive(Clone, Copy)]
+/// Every value of a parameter to sweep over.  A range is written as a list of
+/// items separated by commas, where each item is one of:
+///
+/// * a single value, such as `8000`
+/// * `START..END`, counting up in steps of 1
+/// * `START..STEP..END`, such as `7990..0.5..8010`
+/// * `log:START..END:COUNT`, giving COUNT values spaced evenly on a log scale
+///
+/// Values may carry a metric prefix and the parameter's unit, so that `19k`
+/// and `19kHz` are both 19000 Hz, and `0.5s` is 500 for a parameter measured
+/// in milliseconds.  Integer parameters may also be written in hex, octal or
+/// binary.
+#[derive(Clone, Debug)]
 pub struct SteppedRange {
-    pub start: u32,
-    pub end: u32,
-    pub step: u32,
-    offset: u32,
+    values: Vec<Value>,
+}
+
+/// One value of a range.  Whole numbers are kept exactly rather than as
+/// floating point, so that a seed near `u64::MAX` keeps every digit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Integer(i128),
+    Real(f64),
+}
+
+impl Value {
+    fn as_f64(self) -> f64 {
+        match self {
+            Value::Integer(n) => n as f64,
+            Value::Real(x) => x,
+        }
+    }
+}
+
+impl core::fmt::Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Real(x) => write!(f, "{}", x),
+        }
+    }
 }
 
 pub enum SteppedRangeError {
     NoInputProvided,
     NumberParseError(String, num::ParseIntError),
+    FloatParseError(String, num::ParseFloatError),
     UnparseableRange(String),
-    EndLessThanStart(u32,u32),
+    EndLessThanStart(f64, f64),
     StepIsZero,
+    NotAnInteger(f64),
+    UnknownUnit(String),
+    BadLogCount(String),
+    LogNotPositive(f64),
 }
 
 impl core::fmt::Debug for SteppedRangeError {
@@ -24,16 +63,68 @@ impl core::fmt::Debug for SteppedRangeError {
             SteppedRangeError::NumberParseError(s, e) => {
                 write!(f, "unable to parse {}: {:?}", s, e)
             }
+            SteppedRangeError::FloatParseError(s, e) => {
+                write!(f, "unable to parse {}: {:?}", s, e)
+            }
             SteppedRangeError::UnparseableRange(s) => write!(f, "unable to parse range {}", s),
-            SteppedRangeError::EndLessThanStart(start, end) => write!(f, "range end {} is less than the start {}", end, start),
+            SteppedRangeError::EndLessThanStart(start, end) => {
+                write!(f, "range end {} is less than the start {}", end, start)
+            }
             SteppedRangeError::StepIsZero => write!(f, "step value is zero"),
+            SteppedRangeError::NotAnInteger(n) => write!(f, "{} is not a whole number", n),
+            SteppedRangeError::UnknownUnit(s) => write!(f, "unknown unit in {}", s),
+            SteppedRangeError::BadLogCount(s) => {
+                write!(f, "log range {} needs a count of at least 2", s)
+            }
+            SteppedRangeError::LogNotPositive(n) => {
+                write!(f, "log range bound {} is not greater than zero", n)
+            }
+        }
+    }
+}
+
+/// The unit a parameter is measured in, which decides the suffixes its
+/// values may have
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    None,
+    Hertz,
+    Milliseconds,
+    Decibels,
+}
+
+impl Unit {
+    /// The symbol for the unit, and how many of the parameter's unit it
+    /// stands for
+    fn symbol(self) -> Option<(&'static str, f64)> {
+        match self {
+            Unit::None => None,
+            Unit::Hertz => Some(("Hz", 1.0)),
+            Unit::Milliseconds => Some(("s", 1000.0)),
+            Unit::Decibels => Some(("dB", 1.0)),
         }
     }
 }
 
-fn parse_u32(value: &str) -> Result<u32, SteppedRangeError> {
+const METRIC_PREFIXES: &[(&str, f64)] = &[
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+];
+
+/// Round away the error accumulated when stepping through a range or
+/// scaling by a prefix, so that `0.1..0.1..0.3` gives `0.3` rather than
+/// `0.30000000000000004`.
+fn tidy(value: f64) -> f64 {
+    format!("{:.12e}", value).parse::<f64>().unwrap_or(value)
+}
+
+fn parse_unsigned(value: &str) -> Result<u64, SteppedRangeError> {
     let (value, base) = get_base(value);
-    match u32::from_str_radix(value, base) {
+    match u64::from_str_radix(value, base) {
         Ok(o) => Ok(o),
         Err(e) => Err(SteppedRangeError::NumberParseError(value.to_owned(), e)),
     }
@@ -55,65 +146,334 @@ pub fn get_base(value: &str) -> (&str, u32) {
     }
 }
 
-impl SteppedRange {
-    pub fn parse(input: &str) -> Result<SteppedRange, SteppedRangeError> {
-        let pieces: Vec<&str> = input.split("..").collect();
-        match pieces.len() {
-            0 => Err(SteppedRangeError::NoInputProvided),
-            1 => {
-                let n = parse_u32(pieces[0])?;
-                Ok(SteppedRange {
-                    start: n,
-                    end: n,
-                    step: 1,
-                    offset: n,
-                })
+/// Parse a single value, along with any prefix and unit
+fn parse_value(value: &str, unit: Unit, integer: bool) -> Result<Value, SteppedRangeError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(SteppedRangeError::NoInputProvided);
+    }
+    if integer {
+        // Something like `0.5s` starts out looking like octal, so only take
+        // the value in another base if it really is one
+        if get_base(value).1 != 10 {
+            if let Ok(number) = parse_unsigned(value) {
+                return Ok(Value::Integer(number as i128));
             }
-            2 => {
-                let start = parse_u32(pieces[0])?;
-                let end = parse_u32(pieces[1])?;
-                if end < start {
-                    return Err(SteppedRangeError::EndLessThanStart(start, end));
-                }
-                Ok(SteppedRange {
-                    start,
-                    end,
-                    step: 1,
-                    offset: start,
-                })
+        }
+        if let Ok(number) = value.parse::<i128>() {
+            return Ok(Value::Integer(number));
+        }
+    }
+
+    let mut number = value;
+    let mut scale = 1.0;
+    if let Some((symbol, symbol_scale)) = unit.symbol() {
+        if let Some(rest) = number.strip_suffix(symbol) {
+            number = rest;
+            scale = symbol_scale;
+        }
+    }
+    for (prefix, prefix_scale) in METRIC_PREFIXES {
+        if let Some(rest) = number.strip_suffix(prefix) {
+            number = rest;
+            scale *= prefix_scale;
+            break;
+        }
+    }
+    let number = number.trim().parse::<f64>().map_err(|e| {
+        if number.chars().any(|c| c.is_alphabetic()) {
+            SteppedRangeError::UnknownUnit(value.to_owned())
+        } else {
+            SteppedRangeError::FloatParseError(value.to_owned(), e)
+        }
+    })?;
+    let number = tidy(number * scale);
+    if !integer {
+        return Ok(Value::Real(number));
+    }
+    if number.fract() != 0.0 {
+        return Err(SteppedRangeError::NotAnInteger(number));
+    }
+    Ok(Value::Integer(number as i128))
+}
+
+/// Expand one item of a list
+fn parse_item(item: &str, unit: Unit, integer: bool) -> Result<Vec<Value>, SteppedRangeError> {
+    if let Some(log) = item.strip_prefix("log:") {
+        let mut fields = log.rsplitn(2, ':');
+        let count = fields.next().unwrap_or("");
+        let bounds = fields
+            .next()
+            .ok_or_else(|| SteppedRangeError::BadLogCount(item.to_owned()))?;
+        let count = match count.trim().parse::<usize>() {
+            Ok(count) if count >= 2 => count,
+            _ => return Err(SteppedRangeError::BadLogCount(item.to_owned())),
+        };
+        let (start, end) = match bounds.split("..").collect::<Vec<&str>>().as_slice() {
+            [start, end] => (
+                parse_value(start, unit, false)?.as_f64(),
+                parse_value(end, unit, false)?.as_f64(),
+            ),
+            _ => return Err(SteppedRangeError::UnparseableRange(item.to_owned())),
+        };
+        for bound in &[start, end] {
+            if *bound <= 0.0 {
+                return Err(SteppedRangeError::LogNotPositive(*bound));
             }
-            3 => {
-                let start = parse_u32(pieces[0])?;
-                let step = parse_u32(pieces[1])?;
-                let end = parse_u32(pieces[2])?;
-                if end < start {
-                    return Err(SteppedRangeError::EndLessThanStart(start, end));
-                }
-                if step == 0 {
-                    return Err(SteppedRangeError::StepIsZero);
+        }
+        if end < start {
+            return Err(SteppedRangeError::EndLessThanStart(start, end));
+        }
+        let ratio = (end / start).ln() / (count - 1) as f64;
+        let values = (0..count)
+            .map(|i| tidy(start * (ratio * i as f64).exp()))
+            .map(|value| {
+                if integer {
+                    Value::Integer(value.round() as i128)
+                } else {
+                    Value::Real(value)
                 }
-                Ok(SteppedRange {
-                    start,
-                    step,
-                    end,
-                    offset: start,
-                })
+            })
+            .collect();
+        return Ok(values);
+    }
+
+    let pieces = item
+        .split("..")
+        .map(|piece| parse_value(piece, unit, integer))
+        .collect::<Result<Vec<Value>, SteppedRangeError>>()?;
+    let (start, step, end) = match pieces.as_slice() {
+        [value] => return Ok(vec![*value]),
+        [start, end] => (*start, None, *end),
+        [start, step, end] => (*start, Some(*step), *end),
+        _ => return Err(SteppedRangeError::UnparseableRange(item.to_owned())),
+    };
+    if end.as_f64() < start.as_f64() {
+        return Err(SteppedRangeError::EndLessThanStart(
+            start.as_f64(),
+            end.as_f64(),
+        ));
+    }
+    match (start, step.unwrap_or(Value::Integer(1)), end) {
+        (Value::Integer(start), Value::Integer(step), Value::Integer(end)) => {
+            if step <= 0 {
+                return Err(SteppedRangeError::StepIsZero);
             }
-            _ => Err(SteppedRangeError::UnparseableRange(input.to_owned())),
+            let count = (end - start) / step + 1;
+            Ok((0..count)
+                .map(|i| Value::Integer(start + i * step))
+                .collect())
+        }
+        (start, step, end) => {
+            let (start, step, end) = (start.as_f64(), step.as_f64(), end.as_f64());
+            if step <= 0.0 {
+                return Err(SteppedRangeError::StepIsZero);
+            }
+            let count = ((end - start) / step + 1e-9).floor() as usize + 1;
+            Ok((0..count)
+                .map(|i| Value::Real(tidy(start + i as f64 * step)))
+                .collect())
+        }
+    }
+}
+
+impl SteppedRange {
+    /// Parse a range of plain numbers
+    pub fn parse(input: &str) -> Result<SteppedRange, SteppedRangeError> {
+        SteppedRange::parse_with_unit(input, Unit::None)
+    }
+
+    pub fn parse_with_unit(input: &str, unit: Unit) -> Result<SteppedRange, SteppedRangeError> {
+        SteppedRange::parse_list(input, unit, false)
+    }
+
+    /// Parse a range where every value must be a whole number
+    pub fn parse_integer(input: &str, unit: Unit) -> Result<SteppedRange, SteppedRangeError> {
+        SteppedRange::parse_list(input, unit, true)
+    }
+
+    fn parse_list(
+        input: &str,
+        unit: Unit,
+        integer: bool,
+    ) -> Result<SteppedRange, SteppedRangeError> {
+        if input.trim().is_empty() {
+            return Err(SteppedRangeError::NoInputProvided);
+        }
+        let mut values = vec![];
+        for item in input.split(',') {
+            values.extend(parse_item(item.trim(), unit, integer)?);
         }
+        Ok(SteppedRange { values })
+    }
+}
+
+impl IntoIterator for SteppedRange {
+    type Item = Value;
+    type IntoIter = std::vec::IntoIter<Value>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
     }
 }
 
-impl Iterator for SteppedRange {
-    type Item = u32;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.offset > self.end {
-            self.offset = self.start;
-            None
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reals(input: &str, unit: Unit) -> Vec<f64> {
+        SteppedRange::parse_with_unit(input, unit)
+            .unwrap_or_else(|e| panic!("{}: {:?}", input, e))
+            .into_iter()
+            .map(Value::as_f64)
+            .collect()
+    }
+
+    fn integers(input: &str, unit: Unit) -> Vec<String> {
+        SteppedRange::parse_integer(input, unit)
+            .unwrap_or_else(|e| panic!("{}: {:?}", input, e))
+            .into_iter()
+            .map(|value| value.to_string())
+            .collect()
+    }
+
+    fn error(input: &str, integer: bool) -> SteppedRangeError {
+        let range = if integer {
+            SteppedRange::parse_integer(input, Unit::Hertz)
         } else {
-            let ret_val = self.offset;
-            self.offset += self.step;
-            Some(ret_val)
+            SteppedRange::parse_with_unit(input, Unit::Hertz)
+        };
+        match range {
+            Ok(range) => panic!("{} parsed as {:?}", input, range),
+            Err(e) => e,
         }
     }
+
+    #[test]
+    fn fractional_step() {
+        let values = reals("7990..0.5..8010", Unit::None);
+        assert_eq!(values.len(), 41);
+        assert_eq!(values[0], 7990.0);
+        assert_eq!(values[1], 7990.5);
+        assert_eq!(values[40], 8010.0);
+        assert_eq!(reals("0.1..0.1..0.3", Unit::None), [0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            reals("8000,8013,8100", Unit::None),
+            [8000.0, 8013.0, 8100.0]
+        );
+        assert_eq!(reals("1..3, 10", Unit::None), [1.0, 2.0, 3.0, 10.0]);
+    }
+
+    #[test]
+    fn logarithmic() {
+        let values = reals("log:1k..100k:5", Unit::Hertz);
+        assert_eq!(values.len(), 5);
+        assert_eq!(values[0], 1000.0);
+        assert_eq!(values[2], 10000.0);
+        assert_eq!(values[4], 100000.0);
+        assert!((values[1] - 3162.2776601683795).abs() < 1e-6);
+        assert_eq!(integers("log:1..100:3", Unit::None), ["1", "10", "100"]);
+    }
+
+    #[test]
+    fn units_and_prefixes() {
+        assert_eq!(reals("19kHz", Unit::Hertz), [19000.0]);
+        assert_eq!(reals("19k", Unit::Hertz), [19000.0]);
+        assert_eq!(reals("20ms", Unit::Milliseconds), [20.0]);
+        assert_eq!(integers("0.5s", Unit::Milliseconds), ["500"]);
+        assert_eq!(
+            integers("1s..500ms..2s", Unit::Milliseconds),
+            ["1000", "1500", "2000"]
+        );
+        assert_eq!(reals("-3dB", Unit::Decibels), [-3.0]);
+    }
+
+    #[test]
+    fn negative_ranges() {
+        let expected: Vec<String> = (-6..=6).map(|n: i32| n.to_string()).collect();
+        assert_eq!(integers("-6..6", Unit::None), expected);
+        assert_eq!(reals("-6..6", Unit::None).len(), 13);
+        assert_eq!(reals("-1..0.5..0", Unit::None), [-1.0, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn other_bases() {
+        assert_eq!(integers("0x10", Unit::None), ["16"]);
+        assert_eq!(integers("0b101", Unit::None), ["5"]);
+        assert_eq!(integers("010", Unit::None), ["8"]);
+        // Looks like octal, but isn't
+        assert_eq!(reals("0.5", Unit::None), [0.5]);
+        assert_eq!(integers("0.5s", Unit::Milliseconds), ["500"]);
+        assert!(matches!(
+            error("0.5", true),
+            SteppedRangeError::NotAnInteger(_)
+        ));
+    }
+
+    #[test]
+    fn integers_keep_every_digit() {
+        assert_eq!(
+            integers("18446744073709551615", Unit::None),
+            ["18446744073709551615"]
+        );
+        assert_eq!(
+            integers("0xffffffffffffffff", Unit::None),
+            ["18446744073709551615"]
+        );
+        assert_eq!(
+            integers("9007199254740993..9007199254740995", Unit::None),
+            ["9007199254740993", "9007199254740994", "9007199254740995"]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            error("", false),
+            SteppedRangeError::NoInputProvided
+        ));
+        assert!(matches!(
+            error("10..5", false),
+            SteppedRangeError::EndLessThanStart(start, end) if start == 10.0 && end == 5.0
+        ));
+        assert!(matches!(
+            error("1..0..5", false),
+            SteppedRangeError::StepIsZero
+        ));
+        assert!(matches!(
+            error("1..0..5", true),
+            SteppedRangeError::StepIsZero
+        ));
+        assert!(matches!(
+            error("1..2..3..4", false),
+            SteppedRangeError::UnparseableRange(_)
+        ));
+        assert!(matches!(
+            error("2.5", true),
+            SteppedRangeError::NotAnInteger(_)
+        ));
+        assert!(matches!(
+            error("5kV", false),
+            SteppedRangeError::UnknownUnit(_)
+        ));
+        assert!(matches!(
+            error("1.2.3", false),
+            SteppedRangeError::FloatParseError(..)
+        ));
+        assert!(matches!(
+            error("log:1..10:1", false),
+            SteppedRangeError::BadLogCount(_)
+        ));
+        assert!(matches!(
+            error("log:1..10", false),
+            SteppedRangeError::BadLogCount(_)
+        ));
+        assert!(matches!(
+            error("log:0..10:5", false),
+            SteppedRangeError::LogNotPositive(_)
+        ));
+    }
 }
@@ -0,0 +1,180 @@
+use crate::steppedrange::{SteppedRange, SteppedRangeError, Unit};
+
+/// How the values of a swept parameter are written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Whole numbers in the given unit, where each value may be a
+    /// `SteppedRange`
+    Integer(Unit),
+
+    /// Real numbers in the given unit, where each value may be a
+    /// `SteppedRange`
+    Float(Unit),
+
+    /// Anything else, such as the name of a mode or a file, taken verbatim
+    Text,
+}
+
+impl Kind {
+    /// Expand one value as given on the command line into every value it
+    /// covers
+    fn expand(self, value: &str) -> Result<Vec<String>, SteppedRangeError> {
+        let range = match self {
+            Kind::Integer(unit) => SteppedRange::parse_integer(value, unit)?,
+            Kind::Float(unit) => SteppedRange::parse_with_unit(value, unit)?,
+            Kind::Text => return Ok(vec![value.to_owned()]),
+        };
+        Ok(range.into_iter().map(|v| v.to_string()).collect())
+    }
+}
+
+/// A parameter and every value it takes during the sweep
+#[derive(Clone, Debug)]
+pub struct Dimension {
+    /// The name of the command-line option the values came from
+    pub name: &'static str,
+
+    /// The heading of the parameter's CSV column
+    pub column: &'static str,
+
+    pub values: Vec<String>,
+}
+
+impl Dimension {
+    /// Build a dimension from the values given for an option, each of which
+    /// may stand for several values.  An option that was not given at all
+    /// has the single value `none`.
+    pub fn new<'a>(
+        name: &'static str,
+        column: &'static str,
+        kind: Kind,
+        given: Option<impl Iterator<Item = &'a str>>,
+    ) -> Result<Dimension, SteppedRangeError> {
+        let values = match given {
+            Some(given) => {
+                let mut values = vec![];
+                for value in given {
+                    values.extend(kind.expand(value)?);
+                }
+                values
+            }
+            None => vec!["none".to_owned()],
+        };
+        Ok(Dimension {
+            name,
+            column,
+            values,
+        })
+    }
+}
+
+/// The cartesian product of any number of dimensions.  Each combination is
+/// a point, numbered so that the last dimension changes fastest.
+#[derive(Clone, Debug, Default)]
+pub struct Sweep {
+    dimensions: Vec<Dimension>,
+}
+
+impl Sweep {
+    pub fn new(dimensions: Vec<Dimension>) -> Sweep {
+        Sweep { dimensions }
+    }
+
+    /// The number of points in the sweep
+    pub fn point_count(&self) -> usize {
+        self.dimensions.iter().map(|d| d.values.len()).product()
+    }
+
+    /// The CSV column of each dimension
+    pub fn columns(&self) -> Vec<&'static str> {
+        self.dimensions.iter().map(|d| d.column).collect()
+    }
+
+    /// The number of values of each dimension
+    pub fn sizes(&self) -> Vec<usize> {
+        self.dimensions.iter().map(|d| d.values.len()).collect()
+    }
+
+    /// Every value of the dimension for option `name`
+    pub fn values(&self, name: &str) -> &[String] {
+        self.dimensions
+            .iter()
+            .find(|d| d.name == name)
+            .map(|d| d.values.as_slice())
+            .unwrap_or_else(|| panic!("no dimension named {}", name))
+    }
+
+    /// The index of the point with the given index into each dimension
+    pub fn index(&self, coordinates: &[usize]) -> usize {
+        self.dimensions
+            .iter()
+            .zip(coordinates.iter())
+            .fold(0, |index, (dimension, coordinate)| {
+                index * dimension.values.len() + coordinate
+            })
+    }
+
+    pub fn point(&self, index: usize) -> Point<'_> {
+        let mut remainder = index;
+        let mut values = vec![""; self.dimensions.len()];
+        for (value, dimension) in values.iter_mut().zip(self.dimensions.iter()).rev() {
+            let count = dimension.values.len();
+            *value = &dimension.values[remainder % count];
+            remainder /= count;
+        }
+        Point {
+            sweep: self,
+            values,
+        }
+    }
+}
+
+/// The value of every dimension at one point of a sweep
+pub struct Point<'a> {
+    sweep: &'a Sweep,
+    values: Vec<&'a str>,
+}
+
+impl<'a> Point<'a> {
+    /// The value of the dimension for option `name`.  Panics if there is no
+    /// such dimension, which is a mistake in the caller rather than in the
+    /// user's input.
+    pub fn value(&self, name: &str) -> &'a str {
+        let index = self
+            .sweep
+            .dimensions
+            .iter()
+            .position(|d| d.name == name)
+            .unwrap_or_else(|| panic!("no dimension named {}", name));
+        self.values[index]
+    }
+
+    /// The value of an option that may not have been given
+    pub fn optional(&self, name: &str) -> Option<&'a str> {
+        match self.value(name) {
+            "none" => None,
+            value => Some(value),
+        }
+    }
+
+    pub fn parse<T: std::str::FromStr>(&self, name: &str) -> Result<T, T::Err> {
+        self.value(name).parse::<T>()
+    }
+
+    /// Describe the dimensions that take more than one value
+    pub fn describe(&self) -> String {
+        self.sweep
+            .dimensions
+            .iter()
+            .zip(self.values.iter())
+            .filter(|(dimension, _)| dimension.values.len() > 1)
+            .map(|(dimension, value)| format!("{}: {}  ", dimension.name, value))
+            .collect()
+    }
+
+    /// The value of each column returned by `Sweep::columns()`, with any
+    /// commas replaced so they can't split the cell
+    pub fn csv_values(&self) -> Vec<String> {
+        self.values.iter().map(|v| v.replace(',', " ")).collect()
+    }
+}
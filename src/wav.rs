@@ -1,10 +1,77 @@
 extern crate byteorder;
-use std;
 use std::io::prelude::*;
 use std::fs::File;
-use self::byteorder::{LittleEndian, WriteBytesExt};
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 const FORMAT_PCM  : u16 = 1;
+const FORMAT_IEEE_FLOAT : u16 = 3;
+const FORMAT_EXTENSIBLE : u16 = 0xfffe;
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Read the first channel of a wave file, returning its sample rate and
+/// samples scaled to the range -1 .. 1.  PCM files of 8, 16, 24 or 32 bits
+/// and 32- or 64-bit floating point files are supported.
+pub fn read_wav(filename: &str) -> std::io::Result<(u32, Vec<f64>)> {
+    let mut data = vec![];
+    File::open(filename)?.read_to_end(&mut data)?;
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid_data(&format!("{} is not a wave file", filename)));
+    }
+
+    let mut format = None;
+    let mut position = 12;
+    while position + 8 <= data.len() {
+        let id = &data[position..position + 4];
+        let size = (&data[position + 4..position + 8]).read_u32::<LittleEndian>()? as usize;
+        let body = &data[position + 8..(position + 8 + size).min(data.len())];
+        if id == b"fmt " {
+            let mut fmt = body;
+            let mut audio_format = fmt.read_u16::<LittleEndian>()?;
+            let num_channels = fmt.read_u16::<LittleEndian>()? as usize;
+            let rate = fmt.read_u32::<LittleEndian>()?;
+            let _byte_rate = fmt.read_u32::<LittleEndian>()?;
+            let _block_align = fmt.read_u16::<LittleEndian>()?;
+            let bits_per_sample = fmt.read_u16::<LittleEndian>()?;
+            if audio_format == FORMAT_EXTENSIBLE && body.len() >= 26 {
+                // The real format is the first two bytes of the sub-format GUID
+                audio_format = (&body[24..26]).read_u16::<LittleEndian>()?;
+            }
+            format = Some((audio_format, num_channels.max(1), rate, bits_per_sample));
+        } else if id == b"data" {
+            let (audio_format, num_channels, rate, bits_per_sample) = format
+                .ok_or_else(|| invalid_data(&format!("{}: data before fmt chunk", filename)))?;
+            if bits_per_sample == 0 {
+                return Err(invalid_data(&format!("{}: zero bits per sample", filename)));
+            }
+            let bytes = (bits_per_sample as usize).div_ceil(8);
+            let mut samples = vec![];
+            for frame in body.chunks_exact(bytes * num_channels) {
+                let mut sample = &frame[0..bytes];
+                samples.push(match (audio_format, bits_per_sample) {
+                    (FORMAT_PCM, 8) => (sample[0] as f64 - 128.0) / 128.0,
+                    (FORMAT_PCM, 16) => sample.read_i16::<LittleEndian>()? as f64 / 32768.0,
+                    (FORMAT_PCM, 24) => sample.read_i24::<LittleEndian>()? as f64 / 8388608.0,
+                    (FORMAT_PCM, 32) => sample.read_i32::<LittleEndian>()? as f64 / 2147483648.0,
+                    (FORMAT_IEEE_FLOAT, 32) => sample.read_f32::<LittleEndian>()? as f64,
+                    (FORMAT_IEEE_FLOAT, 64) => sample.read_f64::<LittleEndian>()?,
+                    _ => {
+                        return Err(invalid_data(&format!(
+                            "{}: unsupported format {} with {} bits per sample",
+                            filename, audio_format, bits_per_sample
+                        )))
+                    }
+                });
+            }
+            return Ok((rate, samples));
+        }
+        // Chunks are padded to an even number of bytes
+        position += 8 + size + (size & 1);
+    }
+    Err(invalid_data(&format!("{}: no data chunk found", filename)))
+}
 
 pub fn write_wav(rate: u32, samples: &[i16], file: &mut File) -> std::io::Result<()> {
     let bits_per_sample = 16;
@@ -13,11 +80,11 @@ pub fn write_wav(rate: u32, samples: &[i16], file: &mut File) -> std::io::Result
     /* chunkSize */     file.write_u32::<LittleEndian>(36 + (samples.len() as u32 * (bits_per_sample / 8)))?;
     /* format */        file.write_all(&[0x57, 0x41, 0x56, 0x45])?;        // 'WAVE'
     /* subChunk1Id */   file.write_all(&[0x66, 0x6d, 0x74, 0x20])?;        // 'fmt '
-    /* subChunk1Size */ file.write_u32::<LittleEndian>(16 as u32)?;             // 16 bytes for PCM
+    /* subChunk1Size */ file.write_u32::<LittleEndian>(16)?;                    // 16 bytes for PCM
     /* audioFormat */   file.write_u16::<LittleEndian>(FORMAT_PCM)?;            // 1 = PCM
     /* numChannels */   file.write_u16::<LittleEndian>(num_channels as u16)?;   // 1 = Mono
     /* sampleRate */    file.write_u32::<LittleEndian>(rate)?;                  // Probably 44100
-    /* byteRate */      file.write_u32::<LittleEndian>(rate * num_channels * (bits_per_sample / 8) as u32)?;
+    /* byteRate */      file.write_u32::<LittleEndian>(rate * num_channels * (bits_per_sample / 8))?;
     /* blockAlign */    file.write_u16::<LittleEndian>(num_channels as u16 * (bits_per_sample / 8) as u16)?;
     /* bitsPerSample */ file.write_u16::<LittleEndian>(bits_per_sample as u16)?;
     /* subChunk2Id */   file.write_all(&[0x64, 0x61, 0x74, 0x61])?;        // 'data'